@@ -1,15 +1,14 @@
 #!/bin/bash
 
-export $(cat .env | grep -v '^#' | xargs)
+if [ -f .env ]; then
+  export $(cat .env | grep -v '^#' | xargs)
+fi
 
 export DATABASE_URL="postgres://$POSTGRES_USER:$POSTGRES_PASSWORD@$POSTGRES_SERVER/$POSTGRES_DB"
 
-cargo update
-#cargo install -j 4 diesel_cli
-cargo install diesel_cli
-
-diesel setup
-diesel migration run --locked-schema
-
-#cargo run -j 4 --release
-cargo run --release
+# pending migrations are embedded in the binary & applied on startup (unless RUN_MIGRATIONS=false)
+if [ -x ./cqs-rs ]; then
+  exec ./cqs-rs "$@"
+else
+  exec cargo run --release --bin cqs-rs -- "$@"
+fi
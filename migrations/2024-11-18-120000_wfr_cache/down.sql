@@ -0,0 +1 @@
+DROP TABLE wfr_cache;
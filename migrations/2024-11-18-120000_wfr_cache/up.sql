@@ -0,0 +1,5 @@
+CREATE TABLE wfr_cache (
+  query_hash VARCHAR(64) PRIMARY KEY,
+  date_cached TIMESTAMPTZ NOT NULL,
+  response BYTEA NOT NULL
+);
@@ -0,0 +1,3 @@
+DROP INDEX jobs_status_date_submitted_idx;
+ALTER TABLE jobs DROP COLUMN lease_expires;
+ALTER TABLE jobs DROP COLUMN lease_owner;
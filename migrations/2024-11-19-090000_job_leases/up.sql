@@ -0,0 +1,3 @@
+ALTER TABLE jobs ADD COLUMN lease_owner VARCHAR;
+ALTER TABLE jobs ADD COLUMN lease_expires TIMESTAMPTZ;
+CREATE INDEX jobs_status_date_submitted_idx ON jobs (status, date_submitted);
@@ -0,0 +1,2 @@
+ALTER TABLE jobs DROP COLUMN failure_reason;
+ALTER TABLE jobs DROP COLUMN attempts;
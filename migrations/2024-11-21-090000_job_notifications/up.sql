@@ -0,0 +1,12 @@
+CREATE OR REPLACE FUNCTION notify_queued_job() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('cqs_jobs', NEW.id::text);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER jobs_notify_queued
+    AFTER INSERT OR UPDATE OF status ON jobs
+    FOR EACH ROW
+    WHEN (NEW.status = 'queued')
+    EXECUTE PROCEDURE notify_queued_job();
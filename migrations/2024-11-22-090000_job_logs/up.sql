@@ -0,0 +1,9 @@
+CREATE TABLE job_logs (
+  id SERIAL PRIMARY KEY,
+  job_id INTEGER NOT NULL REFERENCES jobs (id) ON DELETE CASCADE,
+  date_logged TIMESTAMPTZ NOT NULL,
+  level VARCHAR NOT NULL,
+  code VARCHAR,
+  message TEXT NOT NULL
+);
+CREATE INDEX job_logs_job_id_idx ON job_logs (job_id);
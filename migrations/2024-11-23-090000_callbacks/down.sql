@@ -0,0 +1,2 @@
+DROP TABLE callbacks;
+DROP TYPE "Callback_Status_Type";
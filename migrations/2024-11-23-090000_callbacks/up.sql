@@ -0,0 +1,15 @@
+CREATE TYPE "Callback_Status_Type" AS ENUM ('pending', 'delivered', 'dead');
+CREATE TABLE callbacks (
+  id SERIAL PRIMARY KEY,
+  job_id INTEGER NOT NULL REFERENCES jobs (id) ON DELETE CASCADE,
+  url VARCHAR NOT NULL,
+  status "Callback_Status_Type" NOT NULL,
+  date_created TIMESTAMPTZ NOT NULL,
+  date_delivered TIMESTAMPTZ,
+  next_attempt TIMESTAMPTZ NOT NULL,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  last_status_code INTEGER,
+  last_error TEXT
+);
+CREATE INDEX callbacks_job_id_idx ON callbacks (job_id);
+CREATE INDEX callbacks_status_next_attempt_idx ON callbacks (status, next_attempt);
@@ -0,0 +1,16 @@
+DROP TRIGGER IF EXISTS jobs_notify_cancelled ON jobs;
+DROP FUNCTION IF EXISTS notify_cancelled_job();
+
+-- postgres can't drop an enum value, so the type is rebuilt without it
+DROP TRIGGER IF EXISTS jobs_notify_queued ON jobs;
+UPDATE jobs SET status = 'failed' WHERE status = 'cancelled';
+ALTER TYPE "Job_Status_Type" RENAME TO "Job_Status_Type_old";
+CREATE TYPE "Job_Status_Type" AS ENUM ('queued', 'running', 'completed', 'failed');
+ALTER TABLE jobs ALTER COLUMN status TYPE "Job_Status_Type" USING status::text::"Job_Status_Type";
+DROP TYPE "Job_Status_Type_old";
+
+CREATE TRIGGER jobs_notify_queued
+    AFTER INSERT OR UPDATE OF status ON jobs
+    FOR EACH ROW
+    WHEN (NEW.status = 'queued')
+    EXECUTE PROCEDURE notify_queued_job();
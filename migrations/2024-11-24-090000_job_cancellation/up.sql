@@ -0,0 +1,15 @@
+ALTER TYPE "Job_Status_Type" ADD VALUE IF NOT EXISTS 'cancelled';
+
+-- compared as text since a new enum value can't be used in the transaction that adds it
+CREATE OR REPLACE FUNCTION notify_cancelled_job() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('cqs_job_cancellations', NEW.id::text);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER jobs_notify_cancelled
+    AFTER UPDATE OF status ON jobs
+    FOR EACH ROW
+    WHEN (NEW.status::text = 'cancelled')
+    EXECUTE PROCEDURE notify_cancelled_job();
@@ -0,0 +1,3 @@
+ALTER TABLE jobs DROP COLUMN legacy_readable;
+DROP INDEX jobs_public_id_idx;
+ALTER TABLE jobs DROP COLUMN public_id;
@@ -0,0 +1,6 @@
+ALTER TABLE jobs ADD COLUMN public_id UUID NOT NULL DEFAULT gen_random_uuid();
+CREATE UNIQUE INDEX jobs_public_id_idx ON jobs (public_id);
+
+-- jobs submitted before this migration stay readable by their integer id while ALLOW_LEGACY_JOB_IDS is set
+ALTER TABLE jobs ADD COLUMN legacy_readable BOOLEAN NOT NULL DEFAULT true;
+ALTER TABLE jobs ALTER COLUMN legacy_readable SET DEFAULT false;
@@ -0,0 +1,5 @@
+ALTER TABLE jobs DROP COLUMN kg_edge_count;
+ALTER TABLE jobs DROP COLUMN kg_node_count;
+ALTER TABLE jobs DROP COLUMN result_count;
+ALTER TABLE jobs DROP COLUMN response_logs;
+ALTER TABLE jobs DROP COLUMN response_encoding;
@@ -0,0 +1,6 @@
+-- a NULL response_encoding is an uncompressed JSON response, as written before this migration
+ALTER TABLE jobs ADD COLUMN response_encoding VARCHAR;
+ALTER TABLE jobs ADD COLUMN response_logs JSONB;
+ALTER TABLE jobs ADD COLUMN result_count INTEGER;
+ALTER TABLE jobs ADD COLUMN kg_node_count INTEGER;
+ALTER TABLE jobs ADD COLUMN kg_edge_count INTEGER;
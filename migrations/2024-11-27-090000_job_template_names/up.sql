@@ -0,0 +1,2 @@
+-- a NULL template_names runs every template, as jobs submitted before this migration did
+ALTER TABLE jobs ADD COLUMN template_names TEXT[];
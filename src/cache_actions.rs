@@ -0,0 +1,47 @@
+use crate::error::CQSError;
+use crate::model::*;
+use crate::schema::wfr_cache;
+use crate::schema::wfr_cache::dsl::*;
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::upsert::excluded;
+use diesel_async::RunQueryDsl;
+
+fn oldest_fresh_date(ttl_seconds: i64) -> NaiveDateTime {
+    Utc::now().naive_utc() - chrono::Duration::seconds(ttl_seconds)
+}
+
+pub async fn find_fresh(hash: &str, ttl_seconds: i64) -> Result<Option<WFRCacheEntry>, CQSError> {
+    let pool = crate::DB_POOL.get().await;
+    let mut conn = pool.get().await?;
+    let statement = wfr_cache
+        .filter(query_hash.eq(hash))
+        .filter(date_cached.gt(oldest_fresh_date(ttl_seconds)))
+        .select(WFRCacheEntry::as_select());
+    // debug!("{}", diesel::debug_query::<diesel::pg::Pg, _>(&statement).to_string());
+    Ok(statement.first(&mut conn).await.optional()?)
+}
+
+pub async fn upsert(entry: &WFRCacheEntry) -> Result<usize, CQSError> {
+    let pool = crate::DB_POOL.get().await;
+    let mut conn = pool.get().await?;
+    let statement = diesel::insert_into(wfr_cache::table)
+        .values(entry)
+        .on_conflict(query_hash)
+        .do_update()
+        .set((date_cached.eq(excluded(date_cached)), response.eq(excluded(response))));
+    // debug!("{}", diesel::debug_query::<diesel::pg::Pg, _>(&statement).to_string());
+    let num_upserted = statement.execute(&mut conn).await?;
+    debug!("num_upserted: {}", num_upserted);
+    Ok(num_upserted)
+}
+
+pub async fn delete_expired(ttl_seconds: i64) -> Result<usize, CQSError> {
+    let pool = crate::DB_POOL.get().await;
+    let mut conn = pool.get().await?;
+    let statement = diesel::delete(wfr_cache.filter(date_cached.le(oldest_fresh_date(ttl_seconds))));
+    // debug!("{}", diesel::debug_query::<diesel::pg::Pg, _>(&statement).to_string());
+    let num_deleted = statement.execute(&mut conn).await?;
+    debug!("num_deleted: {}", num_deleted);
+    Ok(num_deleted)
+}
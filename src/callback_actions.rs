@@ -0,0 +1,71 @@
+use crate::error::CQSError;
+use crate::model::*;
+use crate::schema::callbacks;
+use crate::schema::callbacks::dsl::*;
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+
+pub async fn find_by_job_id(gid: i32) -> Result<Vec<Callback>, CQSError> {
+    let pool = crate::DB_POOL.get().await;
+    let mut conn = pool.get().await?;
+    let statement = callbacks.filter(job_id.eq(gid)).order(id.asc()).select(Callback::as_select());
+    // debug!("{}", diesel::debug_query::<diesel::pg::Pg, _>(&statement).to_string());
+    Ok(statement.load::<Callback>(&mut conn).await?)
+}
+
+pub async fn insert(new_callback: &NewCallback) -> Result<i32, CQSError> {
+    let pool = crate::DB_POOL.get().await;
+    let mut conn = pool.get().await?;
+    let insert = diesel::insert_into(callbacks::table).values(new_callback);
+    // debug!("{}", diesel::debug_query::<diesel::pg::Pg, _>(&insert).to_string());
+    Ok(insert.returning(id).get_result(&mut conn).await?)
+}
+
+pub async fn claim_due(limit: i64, claim_seconds: i64) -> Result<Vec<Callback>, CQSError> {
+    let pool = crate::DB_POOL.get().await;
+    let mut conn = pool.get().await?;
+    conn.transaction::<_, CQSError, _>(|conn| {
+        async move {
+            let now = Utc::now().naive_utc();
+            let statement = callbacks
+                .filter(status.eq(CallbackStatus::Pending))
+                .filter(next_attempt.le(now))
+                .order(next_attempt.asc())
+                .limit(limit)
+                .for_update()
+                .skip_locked()
+                .select(id);
+            // debug!("{}", diesel::debug_query::<diesel::pg::Pg, _>(&statement).to_string());
+            let due_ids = statement.load::<i32>(conn).await?;
+            if due_ids.is_empty() {
+                return Ok(vec![]);
+            }
+            Ok(diesel::update(callbacks.filter(id.eq_any(due_ids)))
+                .set((attempts.eq(attempts + 1), next_attempt.eq(now + chrono::Duration::seconds(claim_seconds))))
+                .returning(Callback::as_returning())
+                .get_results(conn)
+                .await?)
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+pub async fn update(callback: &Callback) -> Result<(), CQSError> {
+    let pool = crate::DB_POOL.get().await;
+    let mut conn = pool.get().await?;
+    let statement = diesel::update(callbacks::table.filter(id.eq(callback.id))).set(callback);
+    // debug!("{}", diesel::debug_query::<diesel::pg::Pg, _>(&statement).to_string());
+    statement.execute(&mut conn).await?;
+    Ok(())
+}
+
+pub async fn reset_by_job_id(gid: i32) -> Result<usize, CQSError> {
+    let pool = crate::DB_POOL.get().await;
+    let mut conn = pool.get().await?;
+    let statement = diesel::update(callbacks.filter(job_id.eq(gid))).set((status.eq(CallbackStatus::Pending), attempts.eq(0), next_attempt.eq(Utc::now().naive_utc())));
+    // debug!("{}", diesel::debug_query::<diesel::pg::Pg, _>(&statement).to_string());
+    Ok(statement.execute(&mut conn).await?)
+}
@@ -0,0 +1,131 @@
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Responder};
+use rocket::serde::json::Json;
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::{RefOr, Response, Responses};
+use rocket_okapi::response::OpenApiResponderInner;
+use serde_json::{json, Value};
+use std::fmt;
+
+#[derive(Debug)]
+pub enum CQSError {
+    /// No connection could be had to the database
+    DatabaseUnavailable(String),
+    Database(diesel::result::Error),
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    OpenApi(String),
+    JobNotFound(String),
+    TemplateNotFound(String),
+    /// The job is not in a state that allows the request, eg, cancelling a finished job
+    JobConflict(String),
+    InvalidQuery(String),
+    UnsupportedQuery(String),
+}
+
+impl CQSError {
+    pub fn status(&self) -> Status {
+        match self {
+            CQSError::DatabaseUnavailable(_) => Status::ServiceUnavailable,
+            CQSError::Database(_) | CQSError::Io(_) | CQSError::Json(_) | CQSError::OpenApi(_) => Status::InternalServerError,
+            CQSError::JobNotFound(_) | CQSError::TemplateNotFound(_) => Status::NotFound,
+            CQSError::JobConflict(_) => Status::Conflict,
+            CQSError::InvalidQuery(_) => Status::UnprocessableEntity,
+            CQSError::UnsupportedQuery(_) => Status::BadRequest,
+        }
+    }
+}
+
+impl fmt::Display for CQSError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CQSError::DatabaseUnavailable(e) => write!(f, "Database unavailable: {}", e),
+            CQSError::Database(e) => write!(f, "Database error: {}", e),
+            CQSError::Io(e) => write!(f, "IO error: {}", e),
+            CQSError::Json(e) => write!(f, "JSON error: {}", e),
+            CQSError::OpenApi(message) => write!(f, "Could not build OpenAPI document: {}", message),
+            CQSError::JobNotFound(job_id) => write!(f, "Job {} not found", job_id),
+            CQSError::TemplateNotFound(name) => write!(f, "Template {} not found", name),
+            CQSError::JobConflict(message) => write!(f, "{}", message),
+            CQSError::InvalidQuery(message) => write!(f, "Invalid query: {}", message),
+            CQSError::UnsupportedQuery(message) => write!(f, "Unsupported query: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for CQSError {}
+
+impl From<diesel::result::Error> for CQSError {
+    fn from(e: diesel::result::Error) -> Self {
+        CQSError::Database(e)
+    }
+}
+
+impl<E: fmt::Display> From<bb8::RunError<E>> for CQSError {
+    fn from(e: bb8::RunError<E>) -> Self {
+        CQSError::DatabaseUnavailable(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for CQSError {
+    fn from(e: std::io::Error) -> Self {
+        CQSError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for CQSError {
+    fn from(e: serde_json::Error) -> Self {
+        CQSError::Json(e)
+    }
+}
+
+impl<'r> Responder<'r, 'static> for CQSError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let status = self.status();
+        if status.code >= 500 {
+            error!("{}", self);
+        } else {
+            debug!("{}", self);
+        }
+        error_body(status, self.to_string()).respond_to(req)
+    }
+}
+
+fn error_body(status: Status, description: String) -> response::status::Custom<Json<Value>> {
+    response::status::Custom(status, Json(json!({ "status": status.reason_lossy(), "description": description })))
+}
+
+/// same JSON body as CQSError for requests that never reach a route
+#[catch(default)]
+pub fn default_catcher(status: Status, req: &Request) -> response::status::Custom<Json<Value>> {
+    let description = match status.code {
+        400 | 422 => format!("The body of {} {} is not valid TRAPI", req.method(), req.uri().path()),
+        404 => format!("{} {} is not a CQS endpoint", req.method(), req.uri().path()),
+        _ => status.reason_lossy().to_string(),
+    };
+    error_body(status, description)
+}
+
+impl OpenApiResponderInner for CQSError {
+    fn responses(_gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        let mut responses = Responses::default();
+        for (code, description) in [
+            ("400", "The query is valid TRAPI but not one this service answers"),
+            ("404", "No job or template has the given id"),
+            ("409", "The job is not in a state that allows the request"),
+            ("422", "The query is not valid"),
+            ("500", "Unexpected error"),
+            ("503", "The database is unavailable"),
+        ] {
+            responses.responses.insert(
+                code.to_owned(),
+                RefOr::Object(Response {
+                    description: description.to_owned(),
+                    ..Default::default()
+                }),
+            );
+        }
+        Ok(responses)
+    }
+}
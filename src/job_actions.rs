@@ -1,127 +1,210 @@
+use crate::error::CQSError;
 use crate::model::*;
 use crate::schema::jobs;
 use crate::schema::jobs::dsl::*;
+use chrono::Utc;
+use diesel::pg::Pg;
 use diesel::prelude::*;
-use diesel_async::RunQueryDsl;
+use diesel::sql_types::{Bool, Nullable};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, RunQueryDsl};
 
-#[allow(dead_code)]
-pub async fn find_all(limit: Option<i64>) -> Result<Vec<Job>, diesel::result::Error> {
+pub async fn find_by_id(gid: i32) -> Result<Option<Job>, CQSError> {
     let pool = crate::DB_POOL.get().await;
-    match pool.get().await {
-        Ok(mut conn) => {
-            match limit {
-                Some(l) => {
-                    let select_statement = jobs.order(date_submitted.asc()).limit(l).select(Job::as_select());
-                    // debug!("{}", diesel::debug_query::<diesel::pg::Pg, _>(&select_statement).to_string());
-                    select_statement.load(&mut conn).await
-                }
-                None => {
-                    let select_statement = jobs.order(date_submitted.asc()).select(Job::as_select());
-                    // debug!("{}", diesel::debug_query::<diesel::pg::Pg, _>(&select_statement).to_string());
-                    select_statement.load(&mut conn).await
-                }
-            }
-        }
-        Err(e) => {
-            error!("There was a problem getting a connection: {}", e);
-            Ok(vec![])
-        }
-    }
+    let mut conn = pool.get().await?;
+    let job = jobs.filter(id.eq(gid)).select(Job::as_select());
+    // debug!("{}", debug_query::<diesel::pg::Pg, _>(&job).to_string());
+    Ok(job.first(&mut conn).await.optional()?)
 }
 
-pub async fn find_by_id(gid: i32) -> Result<Option<Job>, diesel::result::Error> {
+pub async fn find_by_public_id(gid: &str, allow_legacy_ids: bool) -> Result<Option<Job>, CQSError> {
     let pool = crate::DB_POOL.get().await;
-    match pool.get().await {
-        Ok(mut conn) => {
-            let job = jobs.filter(id.eq(gid)).select(Job::as_select());
+    let mut conn = pool.get().await?;
+    if let Ok(gid) = uuid::Uuid::parse_str(gid) {
+        let job = jobs.filter(public_id.eq(gid)).select(Job::as_select());
+        // debug!("{}", debug_query::<diesel::pg::Pg, _>(&job).to_string());
+        return Ok(job.first(&mut conn).await.optional()?);
+    }
+    match gid.parse::<i32>() {
+        Ok(gid) if allow_legacy_ids => {
+            let job = jobs.filter(id.eq(gid)).filter(legacy_readable.eq(true)).select(Job::as_select());
             // debug!("{}", debug_query::<diesel::pg::Pg, _>(&job).to_string());
-            job.first(&mut conn).await.optional()
-        }
-        Err(e) => {
-            error!("There was a problem getting a connection: {}", e);
-            Ok(None)
+            Ok(job.first(&mut conn).await.optional()?)
         }
+        _ => Ok(None),
     }
 }
 
-pub async fn find_undone() -> Result<Vec<Job>, diesel::result::Error> {
+pub async fn claim_next(owner: &str, lease_seconds: i64) -> Result<Option<Job>, CQSError> {
     let pool = crate::DB_POOL.get().await;
-    match pool.get().await {
-        Ok(mut conn) => {
+    let mut conn = pool.get().await?;
+    let owner = owner.to_string();
+    conn.transaction::<_, CQSError, _>(|conn| {
+        async move {
             let statement = jobs
                 .filter(status.eq(JobStatus::Queued))
                 .order(date_submitted.asc())
-                // .limit(1)
-                .select(Job::as_select());
+                .limit(1)
+                .for_update()
+                .skip_locked()
+                .select(id);
             // debug!("{}", diesel::debug_query::<diesel::pg::Pg, _>(&statement).to_string());
-            statement.load::<Job>(&mut conn).await
-        }
-        Err(e) => {
-            error!("There was a problem getting a connection: {}", e);
-            Ok(vec![])
+            match statement.first::<i32>(conn).await.optional()? {
+                Some(job_id) => {
+                    let now = Utc::now().naive_utc();
+                    let claimed_job = diesel::update(jobs.filter(id.eq(job_id)))
+                        .set((
+                            status.eq(JobStatus::Running),
+                            date_started.eq(Some(now)),
+                            attempts.eq(attempts + 1),
+                            lease_owner.eq(Some(owner)),
+                            lease_expires.eq(Some(now + chrono::Duration::seconds(lease_seconds))),
+                        ))
+                        .returning(Job::as_returning())
+                        .get_result(conn)
+                        .await?;
+                    Ok(Some(claimed_job))
+                }
+                None => Ok(None),
+            }
         }
-    }
+        .scope_boxed()
+    })
+    .await
 }
 
-pub async fn insert(new_job: &NewJob) -> Result<i32, diesel::result::Error> {
+pub async fn renew_lease(gid: i32, owner: &str, lease_seconds: i64) -> Result<bool, CQSError> {
     let pool = crate::DB_POOL.get().await;
-    match pool.get().await {
-        Ok(mut conn) => {
-            let insert = diesel::insert_into(jobs::table).values(new_job);
-            // debug!("{}", diesel::debug_query::<diesel::pg::Pg, _>(&insert).to_string());
-            insert.returning(id).get_result(&mut conn).await
-        }
-        Err(e) => {
-            error!("There was a problem getting a connection: {}", e);
-            Ok(0)
-        }
-    }
+    let mut conn = pool.get().await?;
+    let statement = diesel::update(jobs.filter(id.eq(gid)).filter(lease_owner.eq(owner)).filter(status.eq(JobStatus::Running)))
+        .set(lease_expires.eq(Some(Utc::now().naive_utc() + chrono::Duration::seconds(lease_seconds))));
+    // debug!("{}", diesel::debug_query::<diesel::pg::Pg, _>(&statement).to_string());
+    Ok(statement.execute(&mut conn).await? == 1)
 }
 
-#[allow(dead_code)]
-pub async fn delete(gid: &i32) {
+pub async fn finish(job: &Job, owner: &str) -> Result<bool, CQSError> {
     let pool = crate::DB_POOL.get().await;
-    match pool.get().await {
-        Ok(mut conn) => {
-            let statement = diesel::delete(jobs.filter(id.eq(gid)));
-            // debug!("{}", diesel::debug_query::<diesel::pg::Pg, _>(&statement).to_string());
-            let num_deleted = statement.execute(&mut conn).await;
-            debug!("num_deleted: {}", num_deleted.unwrap());
-            // Ok(num_deleted.unwrap() == 1)
-        }
-        Err(e) => {
-            error!("There was a problem getting a connection: {}", e);
-        }
-    }
+    let mut conn = pool.get().await?;
+    let mut finished_job = job.clone();
+    finished_job.lease_owner = None;
+    finished_job.lease_expires = None;
+    let statement = diesel::update(jobs::table.filter(id.eq(job.id)).filter(lease_owner.eq(owner))).set(&finished_job);
+    // debug!("{}", diesel::debug_query::<diesel::pg::Pg, _>(&statement).to_string());
+    Ok(statement.execute(&mut conn).await? == 1)
 }
 
-pub async fn delete_many(ids: Vec<i32>) {
+pub async fn find_expired_leases() -> Result<Vec<Job>, CQSError> {
     let pool = crate::DB_POOL.get().await;
-    match pool.get().await {
-        Ok(mut conn) => {
-            let statement = diesel::delete(jobs.filter(id.eq_any(ids)));
-            // debug!("{}", diesel::debug_query::<diesel::pg::Pg, _>(&statement).to_string());
-            let num_deleted = statement.execute(&mut conn).await;
-            debug!("num_deleted: {}", num_deleted.unwrap());
-        }
-        Err(e) => {
-            error!("There was a problem getting a connection: {}", e);
-        }
+    let mut conn = pool.get().await?;
+    let statement = jobs
+        .filter(status.eq(JobStatus::Running))
+        .filter(lease_expires.lt(Utc::now().naive_utc()).or(lease_expires.is_null()))
+        .order(date_submitted.asc())
+        .select(Job::as_select());
+    // debug!("{}", diesel::debug_query::<diesel::pg::Pg, _>(&statement).to_string());
+    Ok(statement.load::<Job>(&mut conn).await?)
+}
+
+pub async fn update_if_lease_expired(job: &Job) -> Result<bool, CQSError> {
+    let pool = crate::DB_POOL.get().await;
+    let mut conn = pool.get().await?;
+    let statement = diesel::update(
+        jobs::table
+            .filter(id.eq(job.id))
+            .filter(status.eq(JobStatus::Running))
+            .filter(lease_expires.lt(Utc::now().naive_utc()).or(lease_expires.is_null())),
+    )
+    .set(job);
+    // debug!("{}", diesel::debug_query::<diesel::pg::Pg, _>(&statement).to_string());
+    Ok(statement.execute(&mut conn).await? == 1)
+}
+
+pub async fn cancel(gid: i32, cancellation_response: &JobResponse) -> Result<Option<Job>, CQSError> {
+    let pool = crate::DB_POOL.get().await;
+    let mut conn = pool.get().await?;
+    let statement = diesel::update(jobs.filter(id.eq(gid)).filter(status.eq(JobStatus::Queued).or(status.eq(JobStatus::Running))))
+        .set((
+            status.eq(JobStatus::Cancelled),
+            date_finished.eq(Some(Utc::now().naive_utc())),
+            cancellation_response,
+            lease_owner.eq(None::<String>),
+            lease_expires.eq(None::<chrono::NaiveDateTime>),
+            failure_reason.eq(Some("Cancelled by request".to_string())),
+        ))
+        .returning(Job::as_returning());
+    // debug!("{}", diesel::debug_query::<diesel::pg::Pg, _>(&statement).to_string());
+    Ok(statement.get_result(&mut conn).await.optional()?)
+}
+
+pub async fn insert(new_job: &NewJob) -> Result<uuid::Uuid, CQSError> {
+    let pool = crate::DB_POOL.get().await;
+    let mut conn = pool.get().await?;
+    let insert = diesel::insert_into(jobs::table).values(new_job);
+    // debug!("{}", diesel::debug_query::<diesel::pg::Pg, _>(&insert).to_string());
+    Ok(insert.returning(public_id).get_result(&mut conn).await?)
+}
+
+type RetentionPredicate = Box<dyn BoxableExpression<jobs::table, Pg, SqlType = Nullable<Bool>>>;
+
+fn retention_predicate(policy: &JobRetentionPolicy) -> Option<RetentionPredicate> {
+    let now = Utc::now().naive_utc();
+    let mut rules: Vec<RetentionPredicate> = vec![];
+    if let Some(seconds) = policy.completed_seconds {
+        rules.push(Box::new(status.eq(JobStatus::Completed).and(date_finished.lt(now - chrono::Duration::seconds(seconds)))));
+    }
+    if let Some(seconds) = policy.failed_seconds {
+        rules.push(Box::new(
+            status
+                .eq(JobStatus::Failed)
+                .or(status.eq(JobStatus::Cancelled))
+                .and(date_finished.lt(now - chrono::Duration::seconds(seconds))),
+        ));
+    }
+    if let Some(seconds) = policy.unfinished_seconds {
+        rules.push(Box::new(
+            status
+                .eq(JobStatus::Queued)
+                .or(status.eq(JobStatus::Running))
+                .and(date_submitted.lt(now - chrono::Duration::seconds(seconds)))
+                .nullable(),
+        ));
     }
+    rules.into_iter().reduce(|acc, rule| Box::new(acc.or(rule)))
+}
+
+pub async fn delete_expired(policy: &JobRetentionPolicy) -> Result<usize, CQSError> {
+    let Some(predicate) = retention_predicate(policy) else {
+        return Ok(0);
+    };
+    let pool = crate::DB_POOL.get().await;
+    let mut conn = pool.get().await?;
+    let statement = diesel::delete(jobs.filter(predicate));
+    // debug!("{}", diesel::debug_query::<diesel::pg::Pg, _>(&statement).to_string());
+    Ok(statement.execute(&mut conn).await?)
 }
 
-pub async fn update(job: &Job) {
+pub async fn archive_and_delete_expired<F>(policy: &JobRetentionPolicy, archive: F) -> Result<usize, CQSError>
+where
+    F: Fn(&uuid::Uuid, &[u8], Option<&str>) -> std::io::Result<()> + Send + Sync,
+{
+    let Some(predicate) = retention_predicate(policy) else {
+        return Ok(0);
+    };
     let pool = crate::DB_POOL.get().await;
-    match pool.get().await {
-        Ok(mut conn) => {
-            let statement = diesel::update(jobs::table.filter(id.eq(job.id))).set(job);
+    let mut conn = pool.get().await?;
+    conn.transaction::<_, CQSError, _>(|conn| {
+        async move {
+            let statement = diesel::delete(jobs.filter(predicate)).returning((public_id, response, response_encoding));
             // debug!("{}", diesel::debug_query::<diesel::pg::Pg, _>(&statement).to_string());
-            let num_updated = statement.execute(&mut conn).await;
-            debug!("num_updated: {}", num_updated.unwrap());
-            // Ok(num_updated.unwrap() == 1)
-        }
-        Err(e) => {
-            error!("There was a problem getting a connection: {}", e);
+            let deleted = statement.get_results::<(uuid::Uuid, Option<Vec<u8>>, Option<String>)>(conn).await?;
+            for (job_id, job_response, job_response_encoding) in deleted.iter() {
+                if let Some(job_response) = job_response {
+                    archive(job_id, job_response.as_slice(), job_response_encoding.as_deref())?;
+                }
+            }
+            Ok(deleted.len())
         }
-    }
+        .scope_boxed()
+    })
+    .await
 }
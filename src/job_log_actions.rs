@@ -0,0 +1,23 @@
+use crate::error::CQSError;
+use crate::model::*;
+use crate::schema::job_logs;
+use crate::schema::job_logs::dsl::*;
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+
+pub async fn find_by_job_id(gid: i32) -> Result<Vec<JobLog>, CQSError> {
+    let pool = crate::DB_POOL.get().await;
+    let mut conn = pool.get().await?;
+    let statement = job_logs.filter(job_id.eq(gid)).order((date_logged.asc(), id.asc())).select(JobLog::as_select());
+    // debug!("{}", diesel::debug_query::<diesel::pg::Pg, _>(&statement).to_string());
+    Ok(statement.load::<JobLog>(&mut conn).await?)
+}
+
+pub async fn insert(new_job_log: &NewJobLog) -> Result<(), CQSError> {
+    let pool = crate::DB_POOL.get().await;
+    let mut conn = pool.get().await?;
+    let statement = diesel::insert_into(job_logs::table).values(new_job_log);
+    // debug!("{}", diesel::debug_query::<diesel::pg::Pg, _>(&statement).to_string());
+    statement.execute(&mut conn).await?;
+    Ok(())
+}
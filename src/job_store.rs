@@ -0,0 +1,157 @@
+use crate::error::CQSError;
+use crate::memory_job_store::MemoryJobStore;
+use crate::model::*;
+use crate::{callback_actions, job_actions, job_log_actions};
+use std::env;
+
+/// archives a deleted job's response (public id, stored bytes, content-encoding)
+pub type ArchiveFn<'a> = &'a (dyn Fn(&uuid::Uuid, &[u8], Option<&str>) -> std::io::Result<()> + Send + Sync);
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, strum_macros::Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum JobStoreKind {
+    Postgres,
+    Memory,
+}
+
+/// JOB_STORE, 'postgres' (default) or 'memory'
+pub fn job_store_kind() -> JobStoreKind {
+    match env::var("JOB_STORE").unwrap_or_default().to_lowercase().as_str() {
+        "memory" => JobStoreKind::Memory,
+        _ => JobStoreKind::Postgres,
+    }
+}
+
+lazy_static! {
+    pub static ref JOB_STORE: Box<dyn JobStore> = match job_store_kind() {
+        JobStoreKind::Postgres => Box::new(PostgresJobStore),
+        JobStoreKind::Memory => Box::new(MemoryJobStore::default()),
+    };
+}
+
+/// storage for jobs, their logs & callbacks
+#[rocket::async_trait]
+pub trait JobStore: Send + Sync {
+    async fn find_by_id(&self, gid: i32) -> Result<Option<Job>, CQSError>;
+
+    /// falls back to integer ids when 'allow_legacy_ids'
+    async fn find_by_public_id(&self, gid: &str, allow_legacy_ids: bool) -> Result<Option<Job>, CQSError>;
+
+    async fn insert(&self, new_job: &NewJob) -> Result<uuid::Uuid, CQSError>;
+
+    /// atomically moves the oldest Queued job to Running, leased to 'owner'
+    async fn claim_next(&self, owner: &str, lease_seconds: i64) -> Result<Option<Job>, CQSError>;
+
+    /// false if 'owner' no longer holds the lease
+    async fn renew_lease(&self, gid: i32, owner: &str, lease_seconds: i64) -> Result<bool, CQSError>;
+
+    /// releases the lease, false if 'owner' no longer holds it (so no callback is sent)
+    async fn finish(&self, job: &Job, owner: &str) -> Result<bool, CQSError>;
+
+    async fn find_expired_leases(&self) -> Result<Vec<Job>, CQSError>;
+
+    /// false if the job was renewed or reaped in the meantime
+    async fn update_if_lease_expired(&self, job: &Job) -> Result<bool, CQSError>;
+
+    /// None if the job had already finished
+    async fn cancel(&self, gid: i32, cancellation_response: &JobResponse) -> Result<Option<Job>, CQSError>;
+
+    /// deletes expired jobs along with their logs & callbacks
+    async fn delete_expired(&self, policy: &JobRetentionPolicy) -> Result<usize, CQSError>;
+
+    /// deletes nothing if any response can't be archived
+    async fn archive_and_delete_expired(&self, policy: &JobRetentionPolicy, archive: ArchiveFn<'_>) -> Result<usize, CQSError>;
+
+    async fn find_logs_by_job_id(&self, gid: i32) -> Result<Vec<JobLog>, CQSError>;
+
+    async fn insert_log(&self, new_job_log: &NewJobLog) -> Result<(), CQSError>;
+
+    async fn find_callbacks_by_job_id(&self, gid: i32) -> Result<Vec<Callback>, CQSError>;
+
+    async fn insert_callback(&self, new_callback: &NewCallback) -> Result<i32, CQSError>;
+
+    /// claims due Pending callbacks, pushing their next attempt out by 'claim_seconds'
+    async fn claim_due_callbacks(&self, limit: i64, claim_seconds: i64) -> Result<Vec<Callback>, CQSError>;
+
+    async fn update_callback(&self, callback: &Callback) -> Result<(), CQSError>;
+
+    async fn reset_callbacks_by_job_id(&self, gid: i32) -> Result<usize, CQSError>;
+}
+
+/// The shared store used in deployment, backed by DB_POOL
+pub struct PostgresJobStore;
+
+#[rocket::async_trait]
+impl JobStore for PostgresJobStore {
+    async fn find_by_id(&self, gid: i32) -> Result<Option<Job>, CQSError> {
+        job_actions::find_by_id(gid).await
+    }
+
+    async fn find_by_public_id(&self, gid: &str, allow_legacy_ids: bool) -> Result<Option<Job>, CQSError> {
+        job_actions::find_by_public_id(gid, allow_legacy_ids).await
+    }
+
+    async fn insert(&self, new_job: &NewJob) -> Result<uuid::Uuid, CQSError> {
+        job_actions::insert(new_job).await
+    }
+
+    async fn claim_next(&self, owner: &str, lease_seconds: i64) -> Result<Option<Job>, CQSError> {
+        job_actions::claim_next(owner, lease_seconds).await
+    }
+
+    async fn renew_lease(&self, gid: i32, owner: &str, lease_seconds: i64) -> Result<bool, CQSError> {
+        job_actions::renew_lease(gid, owner, lease_seconds).await
+    }
+
+    async fn finish(&self, job: &Job, owner: &str) -> Result<bool, CQSError> {
+        job_actions::finish(job, owner).await
+    }
+
+    async fn find_expired_leases(&self) -> Result<Vec<Job>, CQSError> {
+        job_actions::find_expired_leases().await
+    }
+
+    async fn update_if_lease_expired(&self, job: &Job) -> Result<bool, CQSError> {
+        job_actions::update_if_lease_expired(job).await
+    }
+
+    async fn cancel(&self, gid: i32, cancellation_response: &JobResponse) -> Result<Option<Job>, CQSError> {
+        job_actions::cancel(gid, cancellation_response).await
+    }
+
+    async fn delete_expired(&self, policy: &JobRetentionPolicy) -> Result<usize, CQSError> {
+        job_actions::delete_expired(policy).await
+    }
+
+    async fn archive_and_delete_expired(&self, policy: &JobRetentionPolicy, archive: ArchiveFn<'_>) -> Result<usize, CQSError> {
+        job_actions::archive_and_delete_expired(policy, archive).await
+    }
+
+    async fn find_logs_by_job_id(&self, gid: i32) -> Result<Vec<JobLog>, CQSError> {
+        job_log_actions::find_by_job_id(gid).await
+    }
+
+    async fn insert_log(&self, new_job_log: &NewJobLog) -> Result<(), CQSError> {
+        job_log_actions::insert(new_job_log).await
+    }
+
+    async fn find_callbacks_by_job_id(&self, gid: i32) -> Result<Vec<Callback>, CQSError> {
+        callback_actions::find_by_job_id(gid).await
+    }
+
+    async fn insert_callback(&self, new_callback: &NewCallback) -> Result<i32, CQSError> {
+        callback_actions::insert(new_callback).await
+    }
+
+    async fn claim_due_callbacks(&self, limit: i64, claim_seconds: i64) -> Result<Vec<Callback>, CQSError> {
+        callback_actions::claim_due(limit, claim_seconds).await
+    }
+
+    async fn update_callback(&self, callback: &Callback) -> Result<(), CQSError> {
+        callback_actions::update(callback).await
+    }
+
+    async fn reset_callbacks_by_job_id(&self, gid: i32) -> Result<usize, CQSError> {
+        callback_actions::reset_by_job_id(gid).await
+    }
+}
@@ -9,37 +9,52 @@ extern crate log;
 #[macro_use]
 extern crate lazy_static;
 
-use crate::model::{JobStatus, NewJob};
-use crate::util::send_callback;
+use crate::error::CQSError;
+use crate::job_store::{job_store_kind, JobStoreKind, JOB_STORE};
+use crate::model::{Callback, JobStatus, NewJob, TemplateSummary};
+use crate::responders::{AcceptEncoding, AdminToken, QueryResponse, TRAPIResponseBody};
 use async_once::AsyncOnce;
+use clap::Parser;
+use diesel::{Connection, PgConnection, RunQueryDsl};
 use diesel_async::pooled_connection::bb8::Pool;
 use diesel_async::pooled_connection::AsyncDieselConnectionManager;
 use diesel_async::AsyncPgConnection;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 use dotenvy::dotenv;
-use futures::future::join_all;
+use peak_alloc::PeakAlloc;
 use reqwest::header;
 use reqwest::redirect::Policy;
 use rocket::fairing::AdHoc;
-use rocket::response::status;
+use rocket::http::ContentType;
 use rocket::serde::json::Json;
-use rocket::{Build, Rocket};
+use rocket::{Build, Rocket, State};
 use rocket_okapi::okapi::openapi3::*;
 use rocket_okapi::{mount_endpoints_and_merged_docs, openapi, openapi_get_routes_spec, swagger_ui::*};
 use serde_json::json;
-use std::collections::HashMap;
 use std::env;
 use std::time::Duration;
+use tokio::sync::Notify;
 use tokio::time::timeout;
-use trapi_model_rs::{AsyncQuery, AsyncQueryResponse, AsyncQueryStatusResponse, KnowledgeGraph, KnowledgeType, Query};
-// use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
-use peak_alloc::PeakAlloc;
+use trapi_model_rs::{AsyncQuery, AsyncQueryResponse, AsyncQueryStatusResponse, Query};
 
 #[global_allocator]
 static PEAK_ALLOC: PeakAlloc = PeakAlloc;
 
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+/// arbitrary key for the advisory lock that keeps replicas from migrating at the same time
+const MIGRATION_LOCK_KEY: i64 = 0x6371_735f_6d69_6772;
+
+mod cache_actions;
+mod callback_actions;
+mod error;
 mod job_actions;
+mod job_log_actions;
+mod job_store;
+mod memory_job_store;
 mod model;
 mod openapi;
+mod responders;
 mod schema;
 mod template;
 mod util;
@@ -80,121 +95,155 @@ lazy_static! {
         }
     });
     pub static ref CQS_INFORES: String = "infores:cqs".to_string();
+    pub static ref CQS_INSTANCE_ID: String = format!("{}-{}", env::var("HOSTNAME").unwrap_or("cqs".to_string()), uuid::Uuid::new_v4());
     pub static ref TRAPI_MESSAGE_RESULT_LIMIT: i32 = 500;
+    pub static ref JOB_WAKEUP: Notify = Notify::new();
+    pub static ref CALLBACK_WAKEUP: Notify = Notify::new();
 }
 
 #[openapi]
-#[post("/asyncquery", data = "<data>")]
-async fn asyncquery(data: Json<AsyncQuery>) -> Result<Json<AsyncQueryResponse>, status::Custom<Json<AsyncQuery>>> {
-    let query: AsyncQuery = data.clone().into_inner();
-
-    if let Some(query_graph) = &query.message.query_graph {
-        if let Some((_edge_key, _edge_value)) = &query_graph.edges.iter().find(|(_k, v)| {
-            if let (Some(predicates), Some(knowledge_type)) = (&v.predicates, &v.knowledge_type) {
-                if predicates.contains(&"biolink:treats".to_string()) && knowledge_type == &KnowledgeType::INFERRED {
-                    return true;
-                }
-            }
-            return false;
-        }) {
-            let job = NewJob::new(JobStatus::Queued, serde_json::to_vec(&query).expect("Could not serialize query"));
-            let job_id = job_actions::insert(&job).await.expect("Could not insert Job into DB");
-            let mut ret = AsyncQueryResponse::new(job_id.to_string());
-            ret.status = Some(JobStatus::Queued.to_string());
-            return Ok(Json(ret));
-        } else {
-            let mut message = query.message.clone();
-            message.results = Some(vec![]);
-            message.knowledge_graph = Some(KnowledgeGraph::new(HashMap::new(), HashMap::new()));
-
-            let mut res = trapi_model_rs::Response::new(message);
-            res.status = Some("Success".to_string());
-            res.workflow = query.workflow.clone();
-            res.biolink_version = Some(env::var("BIOLINK_VERSION").unwrap_or("3.1.2".to_string()));
-            res.schema_version = Some(env::var("TRAPI_VERSION").unwrap_or("1.4.0".to_string()));
-
-            send_callback(query, res).await;
-            warn!("returning: {:?}", status::Custom(rocket::http::Status::Ok, data.clone()));
-            return Err(status::Custom(rocket::http::Status::Ok, data.clone()));
-        }
-    }
-    warn!("returning: {:?}", status::Custom(rocket::http::Status::Ok, data.clone()));
-    Err(status::Custom(rocket::http::Status::Ok, data.clone()))
+#[post("/asyncquery?<templates>", data = "<data>")]
+async fn asyncquery(data: Json<AsyncQuery>, templates: Option<&str>) -> Result<Json<AsyncQueryResponse>, CQSError> {
+    let query: AsyncQuery = data.into_inner();
+
+    // rejected up front, so no job is queued & no callback is sent
+    util::treats_query_ids(&query.message)?;
+    let template_names = util::parse_template_names(templates);
+    util::select_templates(template_names.as_ref())?;
+
+    let mut job = NewJob::new(JobStatus::Queued, serde_json::to_vec(&query).expect("Could not serialize query"));
+    job.template_names = template_names;
+    let job_id = JOB_STORE.insert(&job).await?;
+    JOB_WAKEUP.notify_one();
+    let mut ret = AsyncQueryResponse::new(job_id.to_string());
+    ret.status = Some(JobStatus::Queued.to_string());
+    Ok(Json(ret))
 }
 
 #[openapi]
 #[get("/asyncquery_status/<job_id>")]
-async fn asyncquery_status(job_id: i32) -> Result<Json<AsyncQueryStatusResponse>, status::BadRequest<String>> {
+async fn asyncquery_status(job_id: &str) -> Result<Json<AsyncQueryStatusResponse>, CQSError> {
     debug!("job id: {}", job_id);
-    if let Ok(job_result) = job_actions::find_by_id(job_id).await {
-        if let Some(job) = job_result {
-            let mut status_response = AsyncQueryStatusResponse {
-                status: job.status.to_string(),
-                description: job.status.to_string(),
-                logs: vec![],
-                response_url: Some(format!("{}/download/{}", env::var("RESPONSE_URL").unwrap_or("http://localhost:8000".to_string()), job.id)),
-            };
-
-            if let Some(job_response) = job.response {
-                let response: trapi_model_rs::Response = serde_json::from_str(&*String::from_utf8_lossy(job_response.as_slice())).unwrap();
-                if let Some(logs) = response.logs {
-                    status_response.logs = logs.clone();
-                }
-            }
-            return Ok(Json(status_response));
-        }
+    let job = util::find_job(job_id).await?;
+    let mut status_response = AsyncQueryStatusResponse {
+        status: job.status.to_string(),
+        description: job.failure_reason.clone().unwrap_or(job.status.to_string()),
+        logs: vec![],
+        response_url: Some(format!(
+            "{}/download/{}",
+            env::var("RESPONSE_URL").unwrap_or("http://localhost:8000".to_string()),
+            job.public_id
+        )),
+    };
+
+    // logs are written as the job runs, so they are available before a response exists
+    let job_logs = JOB_STORE.find_logs_by_job_id(job.id).await?;
+    status_response.logs = job_logs.iter().filter_map(|job_log| job_log.to_log_entry()).collect();
+
+    // jobs stored before their logs had a column of their own only have them inside the response
+    if status_response.logs.is_empty() {
+        let logs = match &job.response_logs {
+            Some(response_logs) => serde_json::from_value(response_logs.clone()).ok(),
+            None => util::decode_job_response(&job)
+                .and_then(|job_response| serde_json::from_slice::<trapi_model_rs::Response>(job_response.as_slice()).ok())
+                .and_then(|response| response.logs),
+        };
+        status_response.logs = logs.unwrap_or_default();
     }
-    Err(status::BadRequest("Job not found".to_string()))
+    Ok(Json(status_response))
+}
+
+#[openapi]
+#[delete("/asyncquery/<job_id>")]
+async fn cancel_asyncquery(job_id: &str) -> Result<Json<AsyncQueryStatusResponse>, CQSError> {
+    debug!("cancelling job id: {}", job_id);
+    let job = util::find_job(job_id).await?;
+    let cancelled_job = util::cancel_asyncquery_job(&job).await?;
+    Ok(Json(AsyncQueryStatusResponse {
+        status: cancelled_job.status.to_string(),
+        description: cancelled_job.failure_reason.clone().unwrap_or(cancelled_job.status.to_string()),
+        logs: vec![],
+        response_url: Some(format!(
+            "{}/download/{}",
+            env::var("RESPONSE_URL").unwrap_or("http://localhost:8000".to_string()),
+            cancelled_job.public_id
+        )),
+    }))
 }
 
 #[openapi]
-#[post("/query", data = "<data>")]
-async fn query(data: Json<Query>) -> Json<trapi_model_rs::Response> {
+#[post("/query?<templates>&<dry_run>", data = "<data>")]
+async fn query(data: Json<Query>, templates: Option<&str>, dry_run: Option<bool>, workflow_runner: &State<util::WorkflowRunnerUrl>) -> Result<QueryResponse, CQSError> {
     let query: Query = data.into_inner();
-    let mut responses: Vec<trapi_model_rs::Response> = vec![];
+    util::treats_query_ids(&query.message)?;
+    let cqs_templates = util::select_templates(util::parse_template_names(templates).as_ref())?;
 
-    if let Some(query_graph) = &query.message.query_graph {
-        if let Some((_edge_key, edge_value)) = &query_graph.edges.iter().find(|(_k, v)| {
-            if let (Some(predicates), Some(knowledge_type)) = (&v.predicates, &v.knowledge_type) {
-                if predicates.contains(&"biolink:treats".to_string()) && knowledge_type == &KnowledgeType::INFERRED {
-                    return true;
-                }
-            }
-            return false;
-        }) {
-            if let Some((_node_key, node_value)) = &query_graph.nodes.iter().find(|(k, _v)| *k == &edge_value.object) {
-                if let Some(ids) = &node_value.ids {
-                    let future_responses: Vec<_> = WHITELISTED_TEMPLATE_QUERIES.iter().map(|cqs_query| util::process(&query_graph, cqs_query, &ids)).collect();
-                    let joined_future_responses = join_all(future_responses).await;
-                    joined_future_responses
-                        .into_iter()
-                        .filter_map(std::convert::identity)
-                        .for_each(|trapi_response| responses.push(trapi_response));
-                }
-            }
-        }
+    if dry_run.unwrap_or(false) {
+        return Ok(QueryResponse::DryRun(Json(util::dry_run_templates(
+            &query.message,
+            &cqs_templates,
+            query.bypass_cache.unwrap_or(false),
+        ))));
     }
 
-    let res = util::merge_sort_truncate(query.message.clone(), query.workflow.clone(), responses).await;
+    let progress_log = util::ProgressLog::new(None);
+
+    let template_runs = util::run_templates(&query.message, &cqs_templates, query.bypass_cache.unwrap_or(false), &workflow_runner.0, &progress_log).await;
+    let res = util::merge_template_runs(query.message.clone(), query.workflow.clone(), template_runs, &progress_log).await;
 
     // let node_binding_to_log_odds_map = util::build_node_binding_to_log_odds_data_map(&message.knowledge_graph);
     // let mut ret = trapi_model_rs::Response::new(util::add_composite_score_attributes(message, node_binding_to_log_odds_map));
 
-    Json(res)
+    Ok(QueryResponse::Response(Json(res)))
+}
+
+#[post("/admin/callbacks/<job_id>/redeliver")]
+async fn redeliver_callbacks(job_id: &str, _admin_token: AdminToken) -> Result<Json<Vec<Callback>>, CQSError> {
+    let job = util::find_job(job_id).await?;
+    match job.status {
+        JobStatus::Completed | JobStatus::Failed | JobStatus::Cancelled => Ok(Json(util::redeliver_callbacks(&job).await?)),
+        _ => Err(CQSError::JobConflict(format!("Job {} has not finished", job_id))),
+    }
 }
 
 #[openapi]
 #[get("/download/<job_id>")]
-async fn download(job_id: i32) -> Result<Json<trapi_model_rs::Response>, status::BadRequest<String>> {
-    if let Ok(job_result) = job_actions::find_by_id(job_id).await {
-        if let Some(job) = job_result {
-            if let Some(job_response) = job.response {
-                let response: trapi_model_rs::Response = serde_json::from_str(&*String::from_utf8_lossy(job_response.as_slice())).unwrap();
-                return Ok(Json(response));
-            }
+async fn download(job_id: &str, accept_encoding: AcceptEncoding) -> Result<TRAPIResponseBody, CQSError> {
+    let job = util::find_job(job_id).await?;
+    if job.response_encoding.as_deref() == Some("gzip") && accept_encoding.accepts("gzip") {
+        if let Some(job_response) = job.response {
+            return Ok(TRAPIResponseBody::gzipped(job_response));
         }
     }
-    Err(status::BadRequest("Job not found".to_string()))
+    match util::decode_job_response(&job) {
+        Some(job_response) => Ok(TRAPIResponseBody::json(job_response)),
+        None => Err(CQSError::JobConflict(format!("Job {} has no response yet", job_id))),
+    }
+}
+
+#[openapi]
+#[get("/meta_knowledge_graph")]
+async fn meta_knowledge_graph() -> Result<Json<trapi_model_rs::MetaKnowledgeGraph>, CQSError> {
+    Ok(Json(util::build_meta_knowledge_graph(&WHITELISTED_TEMPLATE_QUERIES)?))
+}
+
+#[openapi]
+#[get("/templates")]
+async fn templates() -> Json<Vec<TemplateSummary>> {
+    Json(WHITELISTED_TEMPLATE_QUERIES.iter().map(util::summarize_template).collect())
+}
+
+#[openapi]
+#[get("/templates/<name>")]
+async fn template_summary(name: &str) -> Result<Json<TemplateSummary>, CQSError> {
+    let cqs_template = util::find_template(name)?;
+    Ok(Json(util::summarize_template(cqs_template)))
+}
+
+#[openapi(skip)]
+#[get("/smartapi.yaml")]
+async fn smartapi() -> Result<(ContentType, String), CQSError> {
+    Ok((ContentType::new("application", "yaml"), smartapi_yaml()?))
 }
 
 #[openapi]
@@ -206,11 +255,91 @@ async fn version() -> serde_json::Value {
     json!({"app_version": app_version, "trapi_version": trapi_version, "maturity": maturity})
 }
 
+#[derive(Parser, PartialEq, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Options {
+    /// Apply pending migrations & exit without starting the server
+    #[clap(long)]
+    migrate_only: bool,
+
+    /// Don't apply pending migrations on startup, same as RUN_MIGRATIONS=false
+    #[clap(long)]
+    skip_migrations: bool,
+
+    /// Print the SmartAPI registration document (YAML) & exit
+    #[clap(long)]
+    smartapi: bool,
+}
+
+fn smartapi_yaml() -> Result<String, CQSError> {
+    let (_routes, routes_spec) = get_routes_and_docs(&rocket_okapi::settings::OpenApiSettings::default());
+    let template_names = WHITELISTED_TEMPLATE_QUERIES.iter().map(|cqs_template| cqs_template.name()).collect();
+    openapi::smartapi_yaml(&routes_spec, template_names, openapi::test_data_location())
+}
+
+/// one replica at a time
+async fn run_pending_migrations() -> Result<(), String> {
+    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    tokio::task::spawn_blocking(move || {
+        let mut conn = PgConnection::establish(&database_url).map_err(|e| e.to_string())?;
+        diesel::sql_query(format!("SELECT pg_advisory_lock({})", MIGRATION_LOCK_KEY))
+            .execute(&mut conn)
+            .map_err(|e| e.to_string())?;
+        let applied = conn
+            .run_pending_migrations(MIGRATIONS)
+            .map(|versions| versions.iter().map(|v| v.to_string()).collect::<Vec<_>>())
+            .map_err(|e| e.to_string());
+        diesel::sql_query(format!("SELECT pg_advisory_unlock({})", MIGRATION_LOCK_KEY))
+            .execute(&mut conn)
+            .map_err(|e| e.to_string())?;
+        applied?.iter().for_each(|version| info!("applied migration: {}", version));
+        Ok(())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
 #[rocket::main]
 async fn main() {
     dotenv().ok();
     env_logger::init();
 
+    let options = Options::parse();
+    debug!("{:?}", options);
+
+    if options.smartapi {
+        if openapi::test_data_location().is_none() {
+            error!("TEST_DATA_LOCATION must be set to build the SmartAPI document");
+            std::process::exit(1);
+        }
+        match smartapi_yaml() {
+            Ok(yaml) => println!("{}", yaml),
+            Err(e) => {
+                error!("Could not build SmartAPI document: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if options.migrate_only || (!options.skip_migrations && util::run_migrations_on_startup()) {
+        if job_store_kind() == JobStoreKind::Postgres {
+            if let Err(e) = run_pending_migrations().await {
+                error!("Could not run migrations: {}", e);
+                std::process::exit(1);
+            }
+        } else {
+            warn!("not running migrations, the {} job store has no database", job_store_kind());
+        }
+    }
+
+    if options.migrate_only {
+        return;
+    }
+
+    // a missing template fails here rather than on the first query
+    lazy_static::initialize(&WHITELISTED_TEMPLATE_QUERIES);
+
     let launch_result = create_server().launch().await;
     match launch_result {
         Ok(_) => info!("Rocket shut down gracefully."),
@@ -220,6 +349,10 @@ async fn main() {
 
 pub fn create_server() -> Rocket<Build> {
     let mut building_rocket = rocket::build()
+        .manage(util::WorkflowRunnerUrl(util::workflow_runner_url()))
+        .register("/", catchers![error::default_catcher])
+        // admin routes are kept out of the OpenAPI spec
+        .mount("/", routes![redeliver_callbacks])
         .mount(
             "/docs/",
             make_swagger_ui(&SwaggerUIConfig {
@@ -227,46 +360,92 @@ pub fn create_server() -> Rocket<Build> {
                 ..Default::default()
             }),
         )
-        .attach(AdHoc::on_liftoff("delete stale asyncquery jobs", |_| {
+        .attach(AdHoc::on_liftoff("delete expired asyncquery jobs", |_| {
             Box::pin(async {
                 tokio::task::spawn(async {
                     let start = tokio::time::Instant::now() + Duration::from_secs(5);
                     let mut interval_timer = tokio::time::interval_at(start, Duration::from_secs(600));
                     loop {
                         interval_timer.tick().await;
-                        match timeout(Duration::from_secs(30), util::delete_stale_asyncquery_jobs()).await {
+                        match timeout(Duration::from_secs(120), util::delete_expired_asyncquery_jobs()).await {
                             Ok(_) => {}
                             Err(_) => {
                                 warn!("deleting asyncquery jobs timed out")
                             }
                         }
+                        match timeout(Duration::from_secs(30), util::delete_expired_wfr_cache_entries()).await {
+                            Ok(_) => {}
+                            Err(_) => {
+                                warn!("deleting expired wfr cache entries timed out")
+                            }
+                        }
                     }
                 });
             })
         }))
-        .attach(AdHoc::on_liftoff("process asyncquery jobs", |_| {
+        .attach(AdHoc::on_liftoff("reap expired asyncquery jobs", |_| {
             Box::pin(async {
                 tokio::task::spawn(async {
-                    let start = tokio::time::Instant::now() + Duration::from_secs(15);
-                    let mut interval_timer = tokio::time::interval_at(start, Duration::from_secs(30));
+                    let start = tokio::time::Instant::now() + Duration::from_secs(10);
+                    let mut interval_timer = tokio::time::interval_at(start, Duration::from_secs(60));
                     loop {
                         interval_timer.tick().await;
-                        if let Ok(undone_jobs) = job_actions::find_undone().await {
-                            debug!("processing async jobs - current memory: {}MB", PEAK_ALLOC.peak_usage_as_mb());
-                            match timeout(Duration::from_secs(450), util::process_asyncquery_jobs(undone_jobs)).await {
-                                Ok(_) => {}
-                                Err(_) => {
-                                    warn!("processing asyncqueries timed out")
-                                }
+                        match timeout(Duration::from_secs(300), util::reap_expired_asyncquery_jobs()).await {
+                            Ok(_) => {}
+                            Err(_) => {
+                                warn!("reaping expired asyncquery jobs timed out")
                             }
                         }
                     }
                 });
             })
+        }))
+        .attach(AdHoc::on_liftoff("listen for asyncquery job notifications", |_| {
+            Box::pin(async {
+                // other replicas only share jobs through Postgres
+                if job_store_kind() == JobStoreKind::Postgres {
+                    tokio::task::spawn(util::listen_for_job_notifications());
+                }
+            })
+        }))
+        .attach(AdHoc::on_liftoff("process asyncquery jobs", |_| {
+            Box::pin(async {
+                tokio::task::spawn(async {
+                    // polling is only a fallback for missed notifications
+                    let mut interval_timer = tokio::time::interval(Duration::from_secs(util::job_poll_seconds()));
+                    loop {
+                        tokio::select! {
+                            _ = JOB_WAKEUP.notified() => {}
+                            _ = interval_timer.tick() => {}
+                        }
+                        debug!("processing async jobs - current memory: {}MB", PEAK_ALLOC.peak_usage_as_mb());
+                        util::process_asyncquery_jobs().await;
+                    }
+                });
+            })
+        }))
+        .attach(AdHoc::on_liftoff("deliver pending callbacks", |_| {
+            Box::pin(async {
+                tokio::task::spawn(async {
+                    // polling picks up retries that have come due & callbacks queued by other replicas
+                    let mut interval_timer = tokio::time::interval(Duration::from_secs(util::callback_poll_seconds()));
+                    loop {
+                        tokio::select! {
+                            _ = CALLBACK_WAKEUP.notified() => {}
+                            _ = interval_timer.tick() => {}
+                        }
+                        util::deliver_pending_callbacks().await;
+                    }
+                });
+            })
         }));
 
     let openapi_settings = rocket_okapi::settings::OpenApiSettings::default();
-    let custom_route_spec = (vec![], openapi::custom_openapi_spec());
+    let test_data_location = openapi::test_data_location();
+    if test_data_location.is_none() {
+        warn!("TEST_DATA_LOCATION is not set, x-trapi.test_data_location is left out of the SmartAPI document");
+    }
+    let custom_route_spec = (vec![], openapi::custom_openapi_spec(test_data_location));
     mount_endpoints_and_merged_docs! {
         building_rocket, "/".to_owned(), openapi_settings,
         "/external" => custom_route_spec,
@@ -276,5 +455,58 @@ pub fn create_server() -> Rocket<Build> {
 }
 
 pub fn get_routes_and_docs(settings: &rocket_okapi::settings::OpenApiSettings) -> (Vec<rocket::Route>, OpenApi) {
-    openapi_get_routes_spec![settings: query, asyncquery, asyncquery_status, download, version/*, view_asyncquery*/]
+    openapi_get_routes_spec![settings: query, asyncquery, cancel_asyncquery, asyncquery_status, download, meta_knowledge_graph, templates, template_summary, smartapi, version/*, view_asyncquery*/]
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::TemplateDryRun;
+    use crate::util::WorkflowRunnerUrl;
+    use rocket::http::{ContentType, Status};
+    use rocket::local::asynchronous::Client;
+    use serde_json::{json, Value};
+    use std::net::TcpListener;
+
+    #[rocket::async_test]
+    async fn dry_run_query_skips_workflow_runner() {
+        let workflow_runner = TcpListener::bind("127.0.0.1:0").unwrap();
+        workflow_runner.set_nonblocking(true).unwrap();
+        let workflow_runner_url = WorkflowRunnerUrl(format!("http://{}", workflow_runner.local_addr().unwrap()));
+
+        let client = Client::tracked(rocket::build().manage(workflow_runner_url).mount("/", routes![super::query]))
+            .await
+            .unwrap();
+        let response = client
+            .post("/query?templates=OpenPredict&dry_run=true")
+            .header(ContentType::JSON)
+            .body(
+                json!({
+                    "message": {
+                        "query_graph": {
+                            "nodes": {"drug": {"categories": ["biolink:ChemicalEntity"]}, "disease": {"categories": ["biolink:Disease"], "ids": ["MONDO:0004979"]}},
+                            "edges": {"t_edge": {"subject": "drug", "object": "disease", "predicates": ["biolink:treats"], "knowledge_type": "inferred"}}
+                        }
+                    }
+                })
+                .to_string(),
+            )
+            .dispatch()
+            .await;
+        assert_eq!(Status::Ok, response.status());
+        let dry_runs: Vec<TemplateDryRun> = response.into_json().await.unwrap();
+        assert_eq!(1, dry_runs.len());
+        assert_eq!("OpenPredict", dry_runs[0].name);
+        assert_eq!(std::io::ErrorKind::WouldBlock, workflow_runner.accept().unwrap_err().kind());
+    }
+
+    #[test]
+    fn query_documents_dry_run_response() {
+        let (_routes, spec) = super::get_routes_and_docs(&rocket_okapi::settings::OpenApiSettings::default());
+        let spec = serde_json::to_value(spec).unwrap();
+        let schemas = spec
+            .pointer("/paths/~1query/post/responses/200/content/application~1json/schema/oneOf")
+            .and_then(Value::as_array)
+            .unwrap();
+        assert_eq!(2, schemas.len());
+    }
 }
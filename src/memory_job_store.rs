@@ -0,0 +1,381 @@
+use crate::error::CQSError;
+use crate::job_store::{ArchiveFn, JobStore};
+use crate::model::*;
+use chrono::Utc;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+#[derive(Default)]
+struct MemoryState {
+    jobs: BTreeMap<i32, Job>,
+    job_logs: Vec<JobLog>,
+    callbacks: BTreeMap<i32, Callback>,
+    last_job_id: i32,
+    last_job_log_id: i32,
+    last_callback_id: i32,
+}
+
+impl MemoryState {
+    fn remove_jobs(&mut self, ids: &Vec<i32>) {
+        self.jobs.retain(|id, _| !ids.contains(id));
+        self.job_logs.retain(|job_log| !ids.contains(&job_log.job_id));
+        self.callbacks.retain(|_, callback| !ids.contains(&callback.job_id));
+    }
+}
+
+/// in-process job store, nothing survives a restart & replicas don't share jobs
+#[derive(Default)]
+pub struct MemoryJobStore {
+    state: Mutex<MemoryState>,
+}
+
+impl MemoryJobStore {
+    fn expired_job_ids(state: &MemoryState, policy: &JobRetentionPolicy) -> Vec<i32> {
+        let now = Utc::now().naive_utc();
+        state.jobs.values().filter(|job| policy.has_expired(job, now)).map(|job| job.id).collect()
+    }
+}
+
+fn has_expired_lease(job: &Job) -> bool {
+    job.status == JobStatus::Running && job.lease_expires.map_or(true, |expires| expires < Utc::now().naive_utc())
+}
+
+#[rocket::async_trait]
+impl JobStore for MemoryJobStore {
+    async fn find_by_id(&self, gid: i32) -> Result<Option<Job>, CQSError> {
+        Ok(self.state.lock().unwrap().jobs.get(&gid).cloned())
+    }
+
+    async fn find_by_public_id(&self, gid: &str, _allow_legacy_ids: bool) -> Result<Option<Job>, CQSError> {
+        // every job in memory has a public id, so there are no legacy ids to fall back to
+        match uuid::Uuid::parse_str(gid) {
+            Ok(gid) => Ok(self.state.lock().unwrap().jobs.values().find(|job| job.public_id == gid).cloned()),
+            Err(_) => Ok(None),
+        }
+    }
+
+    async fn insert(&self, new_job: &NewJob) -> Result<uuid::Uuid, CQSError> {
+        let mut state = self.state.lock().unwrap();
+        state.last_job_id += 1;
+        let job = Job {
+            id: state.last_job_id,
+            status: new_job.status.clone(),
+            date_submitted: new_job.date_submitted,
+            date_started: new_job.date_started,
+            date_finished: new_job.date_finished,
+            query: new_job.query.clone(),
+            response: new_job.response.clone(),
+            lease_owner: new_job.lease_owner.clone(),
+            lease_expires: new_job.lease_expires,
+            attempts: new_job.attempts,
+            failure_reason: new_job.failure_reason.clone(),
+            public_id: new_job.public_id,
+            legacy_readable: false,
+            response_encoding: None,
+            response_logs: None,
+            result_count: None,
+            kg_node_count: None,
+            kg_edge_count: None,
+            template_names: new_job.template_names.clone(),
+        };
+        state.jobs.insert(job.id, job);
+        Ok(new_job.public_id)
+    }
+
+    async fn claim_next(&self, owner: &str, lease_seconds: i64) -> Result<Option<Job>, CQSError> {
+        let mut state = self.state.lock().unwrap();
+        let next_job = state
+            .jobs
+            .values_mut()
+            .filter(|job| job.status == JobStatus::Queued)
+            .min_by_key(|job| (job.date_submitted, job.id));
+        Ok(next_job.map(|job| {
+            let now = Utc::now().naive_utc();
+            job.status = JobStatus::Running;
+            job.date_started = Some(now);
+            job.attempts += 1;
+            job.lease_owner = Some(owner.to_string());
+            job.lease_expires = Some(now + chrono::Duration::seconds(lease_seconds));
+            job.clone()
+        }))
+    }
+
+    async fn renew_lease(&self, gid: i32, owner: &str, lease_seconds: i64) -> Result<bool, CQSError> {
+        match self.state.lock().unwrap().jobs.get_mut(&gid) {
+            Some(job) if job.status == JobStatus::Running && job.lease_owner.as_deref() == Some(owner) => {
+                job.lease_expires = Some(Utc::now().naive_utc() + chrono::Duration::seconds(lease_seconds));
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn finish(&self, job: &Job, owner: &str) -> Result<bool, CQSError> {
+        match self.state.lock().unwrap().jobs.get_mut(&job.id) {
+            Some(stored_job) if stored_job.lease_owner.as_deref() == Some(owner) => {
+                *stored_job = job.clone();
+                stored_job.lease_owner = None;
+                stored_job.lease_expires = None;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn find_expired_leases(&self) -> Result<Vec<Job>, CQSError> {
+        let state = self.state.lock().unwrap();
+        let mut expired_jobs: Vec<Job> = state.jobs.values().filter(|job| has_expired_lease(job)).cloned().collect();
+        expired_jobs.sort_by_key(|job| job.date_submitted);
+        Ok(expired_jobs)
+    }
+
+    async fn update_if_lease_expired(&self, job: &Job) -> Result<bool, CQSError> {
+        match self.state.lock().unwrap().jobs.get_mut(&job.id) {
+            Some(stored_job) if has_expired_lease(stored_job) => {
+                *stored_job = job.clone();
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn cancel(&self, gid: i32, cancellation_response: &JobResponse) -> Result<Option<Job>, CQSError> {
+        match self.state.lock().unwrap().jobs.get_mut(&gid) {
+            Some(job) if job.status == JobStatus::Queued || job.status == JobStatus::Running => {
+                job.status = JobStatus::Cancelled;
+                job.date_finished = Some(Utc::now().naive_utc());
+                job.set_response(cancellation_response.clone());
+                job.lease_owner = None;
+                job.lease_expires = None;
+                job.failure_reason = Some("Cancelled by request".to_string());
+                Ok(Some(job.clone()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    async fn delete_expired(&self, policy: &JobRetentionPolicy) -> Result<usize, CQSError> {
+        let mut state = self.state.lock().unwrap();
+        let expired_ids = MemoryJobStore::expired_job_ids(&state, policy);
+        state.remove_jobs(&expired_ids);
+        Ok(expired_ids.len())
+    }
+
+    async fn archive_and_delete_expired(&self, policy: &JobRetentionPolicy, archive: ArchiveFn<'_>) -> Result<usize, CQSError> {
+        let mut state = self.state.lock().unwrap();
+        let expired_ids = MemoryJobStore::expired_job_ids(&state, policy);
+        for job in expired_ids.iter().filter_map(|id| state.jobs.get(id)) {
+            if let Some(job_response) = &job.response {
+                archive(&job.public_id, job_response.as_slice(), job.response_encoding.as_deref())?;
+            }
+        }
+        state.remove_jobs(&expired_ids);
+        Ok(expired_ids.len())
+    }
+
+    async fn find_logs_by_job_id(&self, gid: i32) -> Result<Vec<JobLog>, CQSError> {
+        // the sort is stable & logs are appended in id order, so this orders them by (date_logged, id)
+        let state = self.state.lock().unwrap();
+        let mut job_logs: Vec<JobLog> = state.job_logs.iter().filter(|job_log| job_log.job_id == gid).cloned().collect();
+        job_logs.sort_by_key(|job_log| job_log.date_logged);
+        Ok(job_logs)
+    }
+
+    async fn insert_log(&self, new_job_log: &NewJobLog) -> Result<(), CQSError> {
+        let mut state = self.state.lock().unwrap();
+        state.last_job_log_id += 1;
+        let job_log = JobLog {
+            id: state.last_job_log_id,
+            job_id: new_job_log.job_id,
+            date_logged: new_job_log.date_logged,
+            level: new_job_log.level.clone(),
+            code: new_job_log.code.clone(),
+            message: new_job_log.message.clone(),
+        };
+        state.job_logs.push(job_log);
+        Ok(())
+    }
+
+    async fn find_callbacks_by_job_id(&self, gid: i32) -> Result<Vec<Callback>, CQSError> {
+        Ok(self.state.lock().unwrap().callbacks.values().filter(|callback| callback.job_id == gid).cloned().collect())
+    }
+
+    async fn insert_callback(&self, new_callback: &NewCallback) -> Result<i32, CQSError> {
+        let mut state = self.state.lock().unwrap();
+        state.last_callback_id += 1;
+        let callback = Callback {
+            id: state.last_callback_id,
+            job_id: new_callback.job_id,
+            url: new_callback.url.clone(),
+            status: new_callback.status.clone(),
+            date_created: new_callback.date_created,
+            date_delivered: None,
+            next_attempt: new_callback.next_attempt,
+            attempts: new_callback.attempts,
+            last_status_code: None,
+            last_error: None,
+        };
+        state.callbacks.insert(callback.id, callback);
+        Ok(state.last_callback_id)
+    }
+
+    async fn claim_due_callbacks(&self, limit: i64, claim_seconds: i64) -> Result<Vec<Callback>, CQSError> {
+        let now = Utc::now().naive_utc();
+        let mut state = self.state.lock().unwrap();
+        let mut due_callbacks: Vec<&mut Callback> = state
+            .callbacks
+            .values_mut()
+            .filter(|callback| callback.status == CallbackStatus::Pending && callback.next_attempt <= now)
+            .collect();
+        due_callbacks.sort_by_key(|callback| callback.next_attempt);
+        Ok(due_callbacks
+            .into_iter()
+            .take(limit as usize)
+            .map(|callback| {
+                callback.attempts += 1;
+                callback.next_attempt = now + chrono::Duration::seconds(claim_seconds);
+                callback.clone()
+            })
+            .collect())
+    }
+
+    async fn update_callback(&self, callback: &Callback) -> Result<(), CQSError> {
+        if let Some(stored_callback) = self.state.lock().unwrap().callbacks.get_mut(&callback.id) {
+            *stored_callback = callback.clone();
+        }
+        Ok(())
+    }
+
+    async fn reset_callbacks_by_job_id(&self, gid: i32) -> Result<usize, CQSError> {
+        let now = Utc::now().naive_utc();
+        let mut state = self.state.lock().unwrap();
+        let mut num_reset = 0;
+        state.callbacks.values_mut().filter(|callback| callback.job_id == gid).for_each(|callback| {
+            callback.status = CallbackStatus::Pending;
+            callback.attempts = 0;
+            callback.next_attempt = now;
+            num_reset += 1;
+        });
+        Ok(num_reset)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::job_store::JobStore;
+    use crate::memory_job_store::MemoryJobStore;
+    use crate::model::{JobResponse, JobRetentionPolicy, JobStatus, NewCallback, NewJob, NewJobLog};
+    use chrono::Utc;
+
+    fn job_response() -> JobResponse {
+        JobResponse {
+            response: Some(b"{}".to_vec()),
+            response_encoding: None,
+            response_logs: None,
+            result_count: Some(0),
+            kg_node_count: Some(0),
+            kg_edge_count: Some(0),
+        }
+    }
+
+    #[tokio::test]
+    async fn claim_and_finish() {
+        let store = MemoryJobStore::default();
+        let first_id = store.insert(&NewJob::new(JobStatus::Queued, b"{}".to_vec())).await.unwrap();
+        let second_id = store.insert(&NewJob::new(JobStatus::Queued, b"{}".to_vec())).await.unwrap();
+
+        let mut job = store.claim_next("replica-a", 300).await.unwrap().unwrap();
+        assert_eq!(first_id, job.public_id);
+        assert_eq!(JobStatus::Running, job.status);
+        assert_eq!(1, job.attempts);
+        assert!(store.renew_lease(job.id, "replica-a", 300).await.unwrap());
+        assert!(!store.renew_lease(job.id, "replica-b", 300).await.unwrap());
+
+        let next_job = store.claim_next("replica-b", 300).await.unwrap().unwrap();
+        assert_eq!(second_id, next_job.public_id);
+        assert!(store.claim_next("replica-b", 300).await.unwrap().is_none());
+
+        job.status = JobStatus::Completed;
+        job.set_response(job_response());
+        assert!(!store.finish(&job, "replica-b").await.unwrap());
+        assert!(store.finish(&job, "replica-a").await.unwrap());
+
+        let finished_job = store.find_by_public_id(&first_id.to_string(), true).await.unwrap().unwrap();
+        assert_eq!(JobStatus::Completed, finished_job.status);
+        assert_eq!(None, finished_job.lease_owner);
+        assert!(store.find_by_public_id(&finished_job.id.to_string(), true).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn expired_leases() {
+        let store = MemoryJobStore::default();
+        store.insert(&NewJob::new(JobStatus::Queued, b"{}".to_vec())).await.unwrap();
+        let mut job = store.claim_next("replica-a", -1).await.unwrap().unwrap();
+
+        let expired_jobs = store.find_expired_leases().await.unwrap();
+        assert_eq!(vec![job.id], expired_jobs.iter().map(|j| j.id).collect::<Vec<_>>());
+
+        job.status = JobStatus::Queued;
+        job.lease_owner = None;
+        job.lease_expires = None;
+        assert!(store.update_if_lease_expired(&job).await.unwrap());
+        assert!(!store.update_if_lease_expired(&job).await.unwrap());
+        assert!(store.find_expired_leases().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn cancel_unfinished_job() {
+        let store = MemoryJobStore::default();
+        let public_id = store.insert(&NewJob::new(JobStatus::Queued, b"{}".to_vec())).await.unwrap();
+        let job = store.find_by_public_id(&public_id.to_string(), false).await.unwrap().unwrap();
+
+        let cancelled_job = store.cancel(job.id, &job_response()).await.unwrap().unwrap();
+        assert_eq!(JobStatus::Cancelled, cancelled_job.status);
+        assert!(store.cancel(job.id, &job_response()).await.unwrap().is_none());
+        assert!(store.claim_next("replica-a", 300).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn delete_expired_jobs_with_their_logs_and_callbacks() {
+        let store = MemoryJobStore::default();
+        store.insert(&NewJob::new(JobStatus::Queued, b"{}".to_vec())).await.unwrap();
+        let mut job = store.claim_next("replica-a", 300).await.unwrap().unwrap();
+        job.status = JobStatus::Failed;
+        job.date_finished = Some(Utc::now().naive_utc() - chrono::Duration::seconds(7200));
+        assert!(store.finish(&job, "replica-a").await.unwrap());
+        store
+            .insert_log(&NewJobLog::new(job.id, Utc::now().naive_utc(), "ERROR".to_string(), None, "failed".to_string()))
+            .await
+            .unwrap();
+        store.insert_callback(&NewCallback::new(job.id, "http://localhost/callback".to_string())).await.unwrap();
+        store.insert(&NewJob::new(JobStatus::Queued, b"{}".to_vec())).await.unwrap();
+
+        let policy = JobRetentionPolicy {
+            completed_seconds: Some(3600),
+            failed_seconds: Some(3600),
+            unfinished_seconds: None,
+            archive_dir: None,
+        };
+        assert_eq!(1, store.delete_expired(&policy).await.unwrap());
+        assert!(store.find_by_id(job.id).await.unwrap().is_none());
+        assert!(store.find_logs_by_job_id(job.id).await.unwrap().is_empty());
+        assert!(store.find_callbacks_by_job_id(job.id).await.unwrap().is_empty());
+        assert_eq!(0, store.delete_expired(&policy).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn claim_due_callbacks() {
+        let store = MemoryJobStore::default();
+        store.insert(&NewJob::new(JobStatus::Queued, b"{}".to_vec())).await.unwrap();
+        let job = store.claim_next("replica-a", 300).await.unwrap().unwrap();
+        store.insert_callback(&NewCallback::new(job.id, "http://localhost/callback".to_string())).await.unwrap();
+
+        let due_callbacks = store.claim_due_callbacks(10, 120).await.unwrap();
+        assert_eq!(1, due_callbacks.len());
+        assert_eq!(1, due_callbacks[0].attempts);
+        assert!(store.claim_due_callbacks(10, 120).await.unwrap().is_empty());
+
+        assert_eq!(1, store.reset_callbacks_by_job_id(job.id).await.unwrap());
+        assert_eq!(1, store.claim_due_callbacks(10, 120).await.unwrap().len());
+    }
+}
@@ -1,4 +1,4 @@
-use crate::schema::sql_types::JobStatusType;
+use crate::schema::sql_types::{CallbackStatusType, JobStatusType};
 use chrono::prelude::*;
 use diesel::deserialize::FromSql;
 use diesel::pg::{Pg, PgValue};
@@ -7,10 +7,11 @@ use diesel::*;
 use rocket_okapi::okapi::schemars;
 use rocket_okapi::okapi::schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt;
 use std::io::Write;
 use strum_macros;
-use trapi_model_rs::{AttributeConstraint, Query, RetrievalSource};
+use trapi_model_rs::{AttributeConstraint, LogEntry, Query, RetrievalSource};
 
 #[allow(dead_code)]
 #[derive(Eq, PartialEq, strum_macros::Display)]
@@ -38,12 +39,50 @@ pub enum KnowledgeLevelType {
     NotProvided,
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema, strum_macros::Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum AttributeAggregation {
+    First,
+    Max,
+    Min,
+    List,
+    Count,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AttributeRule {
+    pub attribute_type_id: String,
+    pub aggregation: AttributeAggregation,
+    pub output_attribute_type_id: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum IntermediateNodeMode {
+    NodeBinding,
+    AuxiliaryGraph,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct IntermediateNode {
+    pub template_node_id: String,
+    pub binding_id: Option<String>,
+    pub mode: IntermediateNodeMode,
+}
+
 #[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize, JsonSchema)]
 pub struct CQS {
     pub scoring_function: Option<String>,
     pub results_limit: Option<f32>,
     pub attribute_type_ids: Option<Vec<String>>,
+    pub attribute_rules: Option<Vec<AttributeRule>>,
+    pub explanation: Option<String>,
+    pub intermediate_nodes: Option<Vec<IntermediateNode>>,
+    /// CURIE prefixes answered for, keyed by template node id
+    pub id_prefixes: Option<BTreeMap<String, Vec<String>>>,
     pub edge_sources: Vec<RetrievalSource>,
+    pub version: Option<String>,
 }
 
 #[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize, JsonSchema)]
@@ -93,6 +132,37 @@ impl QueryTemplate {
     }
 }
 
+/// returned by /query?dry_run=true
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TemplateDryRun {
+    pub name: String,
+    pub query_hash: String,
+    pub query: Query,
+    pub attribute_constraint: Option<AttributeConstraint>,
+}
+
+/// listed by /templates
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TemplateSummary {
+    pub name: String,
+    pub summary: Option<String>,
+    pub poc: Vec<String>,
+    pub predicates: Vec<String>,
+    pub allowlist: Vec<String>,
+    pub edge_sources: Vec<RetrievalSource>,
+    pub scoring_function: String,
+    /// with PINNED_IDS_PLACEHOLDER in place of the disease ids
+    pub query: Query,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, strum_macros::Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum TemplateOutcome {
+    Succeeded,
+    Failed,
+    TimedOut,
+}
+
 #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize, FromSqlRow, AsExpression)]
 #[diesel(sql_type = crate::schema::sql_types::JobStatusType)]
 pub enum JobStatus {
@@ -100,6 +170,7 @@ pub enum JobStatus {
     Running,
     Completed,
     Failed,
+    Cancelled,
 }
 
 impl fmt::Display for JobStatus {
@@ -109,6 +180,7 @@ impl fmt::Display for JobStatus {
             JobStatus::Running => write!(f, "Running"),
             JobStatus::Completed => write!(f, "Completed"),
             JobStatus::Failed => write!(f, "Failed"),
+            JobStatus::Cancelled => write!(f, "Cancelled"),
         }
     }
 }
@@ -120,6 +192,7 @@ impl ToSql<JobStatusType, Pg> for JobStatus {
             JobStatus::Running => out.write_all(b"running")?,
             JobStatus::Completed => out.write_all(b"completed")?,
             JobStatus::Failed => out.write_all(b"failed")?,
+            JobStatus::Cancelled => out.write_all(b"cancelled")?,
         }
         Ok(IsNull::No)
     }
@@ -132,6 +205,7 @@ impl FromSql<JobStatusType, Pg> for JobStatus {
             b"running" => Ok(JobStatus::Running),
             b"completed" => Ok(JobStatus::Completed),
             b"failed" => Ok(JobStatus::Failed),
+            b"cancelled" => Ok(JobStatus::Cancelled),
             _ => Err("Unrecognized enum variant".into()),
         }
     }
@@ -147,6 +221,41 @@ pub struct Job {
     pub date_finished: Option<NaiveDateTime>,
     pub query: Vec<u8>,
     pub response: Option<Vec<u8>>,
+    pub lease_owner: Option<String>,
+    pub lease_expires: Option<NaiveDateTime>,
+    pub attempts: i32,
+    pub failure_reason: Option<String>,
+    pub public_id: uuid::Uuid,
+    pub legacy_readable: bool,
+    pub response_encoding: Option<String>,
+    pub response_logs: Option<serde_json::Value>,
+    pub result_count: Option<i32>,
+    pub kg_node_count: Option<i32>,
+    pub kg_edge_count: Option<i32>,
+    /// The templates to run, all of them if None
+    pub template_names: Option<Vec<String>>,
+}
+
+impl Job {
+    pub fn set_response(&mut self, job_response: JobResponse) {
+        self.response = job_response.response;
+        self.response_encoding = job_response.response_encoding;
+        self.response_logs = job_response.response_logs;
+        self.result_count = job_response.result_count;
+        self.kg_node_count = job_response.kg_node_count;
+        self.kg_edge_count = job_response.kg_edge_count;
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, AsChangeset)]
+#[diesel(table_name = crate::schema::jobs, treat_none_as_null = true)]
+pub struct JobResponse {
+    pub response: Option<Vec<u8>>,
+    pub response_encoding: Option<String>,
+    pub response_logs: Option<serde_json::Value>,
+    pub result_count: Option<i32>,
+    pub kg_node_count: Option<i32>,
+    pub kg_edge_count: Option<i32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Insertable)]
@@ -158,6 +267,12 @@ pub struct NewJob {
     pub date_finished: Option<NaiveDateTime>,
     pub query: Vec<u8>,
     pub response: Option<Vec<u8>>,
+    pub lease_owner: Option<String>,
+    pub lease_expires: Option<NaiveDateTime>,
+    pub attempts: i32,
+    pub failure_reason: Option<String>,
+    pub public_id: uuid::Uuid,
+    pub template_names: Option<Vec<String>>,
 }
 
 impl NewJob {
@@ -169,6 +284,188 @@ impl NewJob {
             date_finished: None,
             query,
             response: None,
+            lease_owner: None,
+            lease_expires: None,
+            attempts: 0,
+            failure_reason: None,
+            public_id: uuid::Uuid::new_v4(),
+            template_names: None,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize, FromSqlRow, AsExpression)]
+#[diesel(sql_type = crate::schema::sql_types::CallbackStatusType)]
+pub enum CallbackStatus {
+    Pending,
+    Delivered,
+    Dead,
+}
+
+impl fmt::Display for CallbackStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CallbackStatus::Pending => write!(f, "Pending"),
+            CallbackStatus::Delivered => write!(f, "Delivered"),
+            CallbackStatus::Dead => write!(f, "Dead"),
+        }
+    }
+}
+
+impl ToSql<CallbackStatusType, Pg> for CallbackStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        match *self {
+            CallbackStatus::Pending => out.write_all(b"pending")?,
+            CallbackStatus::Delivered => out.write_all(b"delivered")?,
+            CallbackStatus::Dead => out.write_all(b"dead")?,
+        }
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<CallbackStatusType, Pg> for CallbackStatus {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match bytes.as_bytes() {
+            b"pending" => Ok(CallbackStatus::Pending),
+            b"delivered" => Ok(CallbackStatus::Delivered),
+            b"dead" => Ok(CallbackStatus::Dead),
+            _ => Err("Unrecognized enum variant".into()),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize, Queryable, Selectable, Identifiable, AsChangeset)]
+#[diesel(table_name = crate::schema::callbacks, treat_none_as_null = true)]
+pub struct Callback {
+    pub id: i32,
+    pub job_id: i32,
+    pub url: String,
+    pub status: CallbackStatus,
+    pub date_created: NaiveDateTime,
+    pub date_delivered: Option<NaiveDateTime>,
+    pub next_attempt: NaiveDateTime,
+    pub attempts: i32,
+    pub last_status_code: Option<i32>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Insertable)]
+#[diesel(table_name = crate::schema::callbacks)]
+pub struct NewCallback {
+    pub job_id: i32,
+    pub url: String,
+    pub status: CallbackStatus,
+    pub date_created: NaiveDateTime,
+    pub next_attempt: NaiveDateTime,
+    pub attempts: i32,
+}
+
+impl NewCallback {
+    pub fn new(job_id: i32, url: String) -> NewCallback {
+        let now = Utc::now().naive_utc();
+        NewCallback {
+            job_id,
+            url,
+            status: CallbackStatus::Pending,
+            date_created: now,
+            next_attempt: now,
+            attempts: 0,
+        }
+    }
+}
+
+/// None keeps those jobs forever
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobRetentionPolicy {
+    pub completed_seconds: Option<i64>,
+    /// also applies to Cancelled jobs
+    pub failed_seconds: Option<i64>,
+    pub unfinished_seconds: Option<i64>,
+    pub archive_dir: Option<std::path::PathBuf>,
+}
+
+impl JobRetentionPolicy {
+    pub fn is_empty(&self) -> bool {
+        self.completed_seconds.is_none() && self.failed_seconds.is_none() && self.unfinished_seconds.is_none()
+    }
+
+    /// finished jobs expire relative to when they finished, others to when they were submitted
+    pub fn has_expired(&self, job: &Job, now: NaiveDateTime) -> bool {
+        let older_than = |date: Option<NaiveDateTime>, seconds: Option<i64>| match (date, seconds) {
+            (Some(date), Some(seconds)) => date < now - chrono::Duration::seconds(seconds),
+            _ => false,
+        };
+        match job.status {
+            JobStatus::Completed => older_than(job.date_finished, self.completed_seconds),
+            JobStatus::Failed | JobStatus::Cancelled => older_than(job.date_finished, self.failed_seconds),
+            JobStatus::Queued | JobStatus::Running => older_than(Some(job.date_submitted), self.unfinished_seconds),
+        }
+    }
+}
+
+pub fn build_log_entry(date_logged: &NaiveDateTime, level: &str, code: &Option<String>, message: &str) -> Option<LogEntry> {
+    serde_json::from_value(serde_json::json!({
+        "timestamp": date_logged.and_utc().to_rfc3339(),
+        "level": level,
+        "code": code,
+        "message": message
+    }))
+    .ok()
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize, Queryable, Selectable, Identifiable)]
+#[diesel(table_name = crate::schema::job_logs)]
+pub struct JobLog {
+    pub id: i32,
+    pub job_id: i32,
+    pub date_logged: NaiveDateTime,
+    pub level: String,
+    pub code: Option<String>,
+    pub message: String,
+}
+
+impl JobLog {
+    pub fn to_log_entry(&self) -> Option<LogEntry> {
+        build_log_entry(&self.date_logged, &self.level, &self.code, &self.message)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Insertable)]
+#[diesel(table_name = crate::schema::job_logs)]
+pub struct NewJobLog {
+    pub job_id: i32,
+    pub date_logged: NaiveDateTime,
+    pub level: String,
+    pub code: Option<String>,
+    pub message: String,
+}
+
+impl NewJobLog {
+    pub fn new(job_id: i32, date_logged: NaiveDateTime, level: String, code: Option<String>, message: String) -> NewJobLog {
+        NewJobLog {
+            job_id,
+            date_logged,
+            level,
+            code,
+            message,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize, Queryable, Selectable, Insertable, AsChangeset)]
+#[diesel(table_name = crate::schema::wfr_cache)]
+pub struct WFRCacheEntry {
+    pub query_hash: String,
+    pub date_cached: NaiveDateTime,
+    pub response: Vec<u8>,
+}
+
+impl WFRCacheEntry {
+    pub fn new(query_hash: String, response: Vec<u8>) -> WFRCacheEntry {
+        WFRCacheEntry {
+            query_hash,
+            date_cached: Utc::now().naive_utc(),
+            response,
         }
     }
 }
@@ -1,12 +1,20 @@
+use crate::error::CQSError;
+use rocket_okapi::okapi::merge::merge_specs;
 use rocket_okapi::okapi::openapi3::{Contact, Info, License, Object, OpenApi, Server};
 use serde_json::json;
 use std::env;
 
-pub fn custom_openapi_spec() -> OpenApi {
+// where the Translator testing harness finds the queries to run against this maturity
+pub fn test_data_location() -> Option<String> {
+    env::var("TEST_DATA_LOCATION").ok().filter(|url| !url.is_empty())
+}
+
+pub fn custom_openapi_spec(test_data_location: Option<String>) -> OpenApi {
     let response_url_root = env::var("RESPONSE_URL").unwrap_or("http://localhost:8000".to_string());
     let maturity = env::var("MATURITY").unwrap_or("development".to_string());
     let location = env::var("LOCATION").unwrap_or("RENCI".to_string());
     let trapi_version = env::var("TRAPI_VERSION").unwrap_or("1.4.0".to_string());
+    let biolink_version = env::var("BIOLINK_VERSION").unwrap_or("4.2.0".to_string());
     OpenApi {
         openapi: OpenApi::default_version(),
         info: Info {
@@ -32,21 +40,32 @@ pub fn custom_openapi_spec() -> OpenApi {
             }),
             version: env!("CARGO_PKG_VERSION").to_owned(),
             extensions: {
-                let raw_extensions = json!({
+                let mut raw_extensions = json!({
                     "x-translator": {
                         "component": "ARA",
                         "team": [ "Clinical Data Provider" ],
-                        "biolink-version": "4.2.0",
-                        "infores": "infores:cqs"
+                        "biolink-version": biolink_version,
+                        "infores": "infores:cqs",
+                        "externalDocs": {
+                            "description": "The values for component and team are restricted according to this external JSON schema. See schema and examples at url",
+                            "url": "https://github.com/NCATSTranslator/translator_extensions/blob/production/x-translator/"
+                        }
                     },
                     "x-trapi": {
                         "version": trapi_version,
                         "asyncquery": true,
                         "operations": [ "lookup" ],
                         "batch_size_limit": 100,
-                        "rate_limit": 10
+                        "rate_limit": 10,
+                        "externalDocs": {
+                            "description": "The values for version are restricted according to the regex in this external JSON schema. See schema and examples at url",
+                            "url": "https://github.com/NCATSTranslator/translator_extensions/blob/production/x-trapi/"
+                        }
                     }
                 });
+                if let Some(url) = test_data_location {
+                    raw_extensions["x-trapi"]["test_data_location"] = json!({ maturity.clone(): { "url": url } });
+                }
                 Object::from_iter(raw_extensions.as_object().unwrap().clone())
             },
         },
@@ -79,3 +98,61 @@ pub fn custom_openapi_spec() -> OpenApi {
         },
     }
 }
+
+/// the SmartAPI registration document
+pub fn smartapi_spec(routes_spec: &OpenApi, template_names: Vec<String>, test_data_location: Option<String>) -> Result<OpenApi, CQSError> {
+    let mut spec = custom_openapi_spec(test_data_location);
+    if let Some(description) = &mut spec.info.description {
+        description.push_str(&format!(" Templates: {}.", template_names.join(", ")));
+    }
+    merge_specs(&mut spec, &"".to_owned(), routes_spec).map_err(|e| CQSError::OpenApi(format!("{:?}", e)))?;
+    Ok(spec)
+}
+
+pub fn smartapi_yaml(routes_spec: &OpenApi, template_names: Vec<String>, test_data_location: Option<String>) -> Result<String, CQSError> {
+    let spec = smartapi_spec(routes_spec, template_names, test_data_location)?;
+    serde_yaml::to_string(&spec).map_err(|e| CQSError::OpenApi(e.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::openapi::smartapi_spec;
+    use serde_json::json;
+    use std::env;
+
+    const TEST_DATA_LOCATION: &str = "https://github.com/NCATSTranslator/Tests/tree/main/test_suites";
+
+    #[test]
+    fn smartapi_spec_lists_translator_extensions() {
+        let (_routes, routes_spec) = crate::get_routes_and_docs(&rocket_okapi::settings::OpenApiSettings::default());
+        let spec = smartapi_spec(
+            &routes_spec,
+            vec!["ClinicalKPs".to_string(), "OpenPredict".to_string()],
+            Some(TEST_DATA_LOCATION.to_string()),
+        )
+        .unwrap();
+
+        let x_translator = spec.info.extensions.get("x-translator").unwrap();
+        assert_eq!(json!("ARA"), x_translator["component"]);
+        assert_eq!(json!("infores:cqs"), x_translator["infores"]);
+        assert!(x_translator["biolink-version"].is_string());
+
+        let x_trapi = spec.info.extensions.get("x-trapi").unwrap();
+        assert_eq!(json!(true), x_trapi["asyncquery"]);
+        assert_eq!(json!(["lookup"]), x_trapi["operations"]);
+        assert!(x_trapi["version"].is_string());
+        let maturity = env::var("MATURITY").unwrap_or("development".to_string());
+        assert_eq!(json!({ maturity: { "url": TEST_DATA_LOCATION } }), x_trapi["test_data_location"]);
+
+        assert!(spec.info.description.unwrap().ends_with(" Templates: ClinicalKPs, OpenPredict."));
+        assert!(spec.paths.contains_key("/query"));
+        assert!(!spec.paths.contains_key("/smartapi.yaml"));
+    }
+
+    #[test]
+    fn smartapi_spec_without_test_data_location() {
+        let (_routes, routes_spec) = crate::get_routes_and_docs(&rocket_okapi::settings::OpenApiSettings::default());
+        let spec = smartapi_spec(&routes_spec, vec![], None).unwrap();
+        assert!(spec.info.extensions.get("x-trapi").unwrap().get("test_data_location").is_none());
+    }
+}
@@ -0,0 +1,172 @@
+use crate::model::TemplateDryRun;
+use rocket::http::{ContentType, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder};
+use rocket::serde::json::Json;
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::{MediaType, RefOr, Response, Responses};
+use rocket_okapi::okapi::schemars::schema::{SchemaObject, SubschemaValidation};
+use rocket_okapi::okapi::Map;
+use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
+use rocket_okapi::response::OpenApiResponderInner;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::io::Cursor;
+
+pub struct AcceptEncoding(Option<String>);
+
+impl AcceptEncoding {
+    /// an entry naming 'encoding' wins over '*', either is refused with q=0
+    pub fn accepts(&self, encoding: &str) -> bool {
+        let Some(accept_encoding) = &self.0 else {
+            return false;
+        };
+        let qualities: Vec<(&str, f32)> = accept_encoding
+            .split(',')
+            .filter_map(|candidate| {
+                let mut parts = candidate.split(';').map(str::trim);
+                let name = parts.next().filter(|name| !name.is_empty())?;
+                let quality = parts
+                    .filter_map(|param| param.split_once('='))
+                    .find(|(key, _)| key.trim().eq_ignore_ascii_case("q"))
+                    .map(|(_, q)| q.trim().parse::<f32>().unwrap_or(0.0))
+                    .unwrap_or(1.0);
+                Some((name, quality))
+            })
+            .collect();
+        let quality_of = |name: &str| qualities.iter().find(|(candidate, _)| candidate.eq_ignore_ascii_case(name)).map(|(_, q)| *q);
+        quality_of(encoding).or_else(|| quality_of("*")).map_or(false, |q| q > 0.0)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AcceptEncoding {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let accept_encoding: Vec<&str> = req.headers().get("Accept-Encoding").collect();
+        request::Outcome::Success(AcceptEncoding(Some(accept_encoding.join(",")).filter(|a| !a.is_empty())))
+    }
+}
+
+impl<'a> OpenApiFromRequest<'a> for AcceptEncoding {
+    fn from_request_input(_gen: &mut OpenApiGenerator, _name: String, _required: bool) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(RequestHeaderInput::None)
+    }
+}
+
+/// 'Authorization: Bearer <ADMIN_TOKEN>', admin routes 404 unless ADMIN_TOKEN is set
+pub struct AdminToken;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminToken {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let Some(admin_token) = env::var("ADMIN_TOKEN").ok().filter(|token| !token.is_empty()) else {
+            return request::Outcome::Error((Status::NotFound, ()));
+        };
+        // digests are compared so the time taken doesn't depend on how much of the token matches
+        match req.headers().get_one("Authorization").and_then(|authorization| authorization.strip_prefix("Bearer ")) {
+            Some(token) if Sha256::digest(token.as_bytes()) == Sha256::digest(admin_token.as_bytes()) => request::Outcome::Success(AdminToken),
+            _ => request::Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
+
+/// sent as stored when gzipped & the client accepts gzip
+pub struct TRAPIResponseBody {
+    body: Vec<u8>,
+    gzipped: bool,
+}
+
+impl TRAPIResponseBody {
+    pub fn json(body: Vec<u8>) -> TRAPIResponseBody {
+        TRAPIResponseBody { body, gzipped: false }
+    }
+
+    pub fn gzipped(body: Vec<u8>) -> TRAPIResponseBody {
+        TRAPIResponseBody { body, gzipped: true }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for TRAPIResponseBody {
+    fn respond_to(self, _req: &'r Request<'_>) -> response::Result<'static> {
+        let mut builder = rocket::Response::build();
+        builder.header(ContentType::JSON).raw_header("Vary", "Accept-Encoding");
+        if self.gzipped {
+            builder.raw_header("Content-Encoding", "gzip");
+        }
+        builder.sized_body(self.body.len(), Cursor::new(self.body)).ok()
+    }
+}
+
+impl OpenApiResponderInner for TRAPIResponseBody {
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        <Json<trapi_model_rs::Response>>::responses(gen)
+    }
+}
+
+pub enum QueryResponse {
+    Response(Json<trapi_model_rs::Response>),
+    DryRun(Json<Vec<TemplateDryRun>>),
+}
+
+impl<'r> Responder<'r, 'static> for QueryResponse {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        match self {
+            QueryResponse::Response(res) => res.respond_to(req),
+            QueryResponse::DryRun(dry_runs) => dry_runs.respond_to(req),
+        }
+    }
+}
+
+impl OpenApiResponderInner for QueryResponse {
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        let schema = SchemaObject {
+            subschemas: Some(Box::new(SubschemaValidation {
+                one_of: Some(vec![gen.json_schema::<trapi_model_rs::Response>().into(), gen.json_schema::<Vec<TemplateDryRun>>().into()]),
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+        let mut content = Map::new();
+        content.insert(
+            "application/json".to_owned(),
+            MediaType {
+                schema: Some(schema),
+                ..Default::default()
+            },
+        );
+        let mut responses = Responses::default();
+        responses.responses.insert(
+            "200".to_owned(),
+            RefOr::Object(Response {
+                description: "The merged TRAPI Response, or with dry_run=true the query each template would send".to_owned(),
+                content,
+                ..Default::default()
+            }),
+        );
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::responders::AcceptEncoding;
+
+    #[test]
+    fn accept_encoding_quality_values() {
+        let accepts_gzip = |header: Option<&str>| AcceptEncoding(header.map(String::from)).accepts("gzip");
+        assert!(accepts_gzip(Some("gzip, deflate, br")));
+        assert!(accepts_gzip(Some("GZIP;q=0.5")));
+        assert!(accepts_gzip(Some("*")));
+        assert!(accepts_gzip(Some("br;q=1.0, *;q=0.1")));
+        assert!(!accepts_gzip(None));
+        assert!(!accepts_gzip(Some("identity")));
+        assert!(!accepts_gzip(Some("gzip;q=0")));
+        assert!(!accepts_gzip(Some("gzip; q=0.000, *")));
+        assert!(!accepts_gzip(Some("*;q=0")));
+        assert!(!accepts_gzip(Some("gzip;q=0, deflate")));
+    }
+}
@@ -4,6 +4,28 @@ pub mod sql_types {
     #[derive(diesel::sql_types::SqlType, QueryId)]
     #[diesel(postgres_type(name = "Job_Status_Type"))]
     pub struct JobStatusType;
+
+    #[derive(diesel::sql_types::SqlType, QueryId)]
+    #[diesel(postgres_type(name = "Callback_Status_Type"))]
+    pub struct CallbackStatusType;
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::CallbackStatusType;
+
+    callbacks (id) {
+        id -> Int4,
+        job_id -> Int4,
+        url -> Varchar,
+        status -> CallbackStatusType,
+        date_created -> Timestamptz,
+        date_delivered -> Nullable<Timestamptz>,
+        next_attempt -> Timestamptz,
+        attempts -> Int4,
+        last_status_code -> Nullable<Int4>,
+        last_error -> Nullable<Text>,
+    }
 }
 
 diesel::table! {
@@ -18,5 +40,41 @@ diesel::table! {
         date_finished -> Nullable<Timestamptz>,
         query -> Bytea,
         response -> Nullable<Bytea>,
+        lease_owner -> Nullable<Varchar>,
+        lease_expires -> Nullable<Timestamptz>,
+        attempts -> Int4,
+        failure_reason -> Nullable<Text>,
+        public_id -> Uuid,
+        legacy_readable -> Bool,
+        response_encoding -> Nullable<Varchar>,
+        response_logs -> Nullable<Jsonb>,
+        result_count -> Nullable<Int4>,
+        kg_node_count -> Nullable<Int4>,
+        kg_edge_count -> Nullable<Int4>,
+        template_names -> Nullable<Array<Text>>,
+    }
+}
+
+diesel::table! {
+    job_logs (id) {
+        id -> Int4,
+        job_id -> Int4,
+        date_logged -> Timestamptz,
+        level -> Varchar,
+        code -> Nullable<Varchar>,
+        message -> Text,
+    }
+}
+
+diesel::table! {
+    wfr_cache (query_hash) {
+        query_hash -> Varchar,
+        date_cached -> Timestamptz,
+        response -> Bytea,
     }
 }
+
+diesel::joinable!(callbacks -> jobs (job_id));
+diesel::joinable!(job_logs -> jobs (job_id));
+
+diesel::allow_tables_to_appear_in_same_query!(callbacks, job_logs, jobs, wfr_cache,);
@@ -1,7 +1,9 @@
 use crate::model::CQSCompositeScoreValue;
 use crate::model::QueryTemplate;
 use crate::util;
+use sha2::{Digest, Sha256};
 use std::fs;
+use std::path::Path;
 
 pub trait CQSTemplate: Send + Sync {
     fn name(&self) -> String;
@@ -9,15 +11,24 @@ pub trait CQSTemplate: Send + Sync {
     fn template_drug_node_id(&self) -> String;
     fn template_disease_node_id(&self) -> String;
     fn compute_score(&self, entry_values: Vec<CQSCompositeScoreValue>) -> f64;
+    fn scoring_function(&self) -> String;
+    fn template_hash(&self) -> String;
+    fn readme(&self) -> Option<String>;
 }
 
 macro_rules! impl_wrapper {
     ($name:ident, $file:literal, $template_drug_node_id:literal, $template_disease_node_id:literal, $func:expr) => {
-        pub struct $name {}
+        pub struct $name {
+            template_hash: String,
+        }
 
         impl $name {
             pub fn new() -> $name {
-                $name {}
+                let file = format!("./templates/{}", $file.to_string());
+                let file_contents = fs::read(file).expect("Could not read template");
+                $name {
+                    template_hash: format!("{:x}", Sha256::digest(file_contents.as_slice())),
+                }
             }
         }
 
@@ -49,6 +60,19 @@ macro_rules! impl_wrapper {
             fn compute_score(&self, entry_values: Vec<CQSCompositeScoreValue>) -> f64 {
                 $func(entry_values)
             }
+
+            fn scoring_function(&self) -> String {
+                stringify!($func).to_string()
+            }
+
+            fn template_hash(&self) -> String {
+                self.template_hash.clone()
+            }
+
+            fn readme(&self) -> Option<String> {
+                let file = Path::new("./templates").join($file);
+                file.parent().and_then(|dir| fs::read_to_string(dir.join("README.md")).ok())
+            }
         }
     };
 }
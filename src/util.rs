@@ -1,18 +1,31 @@
-use crate::model::{AgentType, CQSCompositeScoreKey, CQSCompositeScoreValue, Job, JobStatus, KnowledgeLevelType, QueryTemplate};
-use crate::{job_actions, template, util, CQS_INFORES, REQWEST_CLIENT, WHITELISTED_TEMPLATE_QUERIES};
+use crate::error::CQSError;
+use crate::job_store::{job_store_kind, JobStoreKind, JOB_STORE};
+use crate::model::{build_log_entry, Callback, CallbackStatus, JobResponse, NewCallback, NewJobLog, WFRCacheEntry};
+use crate::model::{
+    AgentType, AttributeAggregation, AttributeRule, CQSCompositeScoreKey, CQSCompositeScoreValue, IntermediateNode, IntermediateNodeMode, Job, JobRetentionPolicy, JobStatus,
+    KnowledgeLevelType, QueryTemplate, TemplateDryRun, TemplateOutcome, TemplateSummary,
+};
+use crate::{cache_actions, template, util, CALLBACK_WAKEUP, CQS_INFORES, CQS_INSTANCE_ID, JOB_WAKEUP, REQWEST_CLIENT, WHITELISTED_TEMPLATE_QUERIES};
 use chrono::Utc;
 use futures::future::join_all;
+use futures::StreamExt;
 use itertools::Itertools;
 use merge_hashmap::Merge;
 use rayon::prelude::*;
-use serde_json::Value;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
 use std::cmp::Ordering;
 use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
 use std::ops::Div;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use std::{env, fs};
+use tokio::sync::{Notify, Semaphore};
+use tokio::time::timeout;
 use trapi_model_rs::{
-    Analysis, AsyncQuery, Attribute, AuxiliaryGraph, BiolinkPredicate, Edge, EdgeBinding, KnowledgeType, Message, NodeBinding, QueryGraph, ResourceRoleEnum, Response, Workflow,
+    Analysis, AsyncQuery, Attribute, AttributeConstraint, AuxiliaryGraph, BiolinkPredicate, Edge, EdgeBinding, KnowledgeGraph, KnowledgeType, LogEntry, Message,
+    MetaKnowledgeGraph, NodeBinding, Query, QueryGraph, ResourceRoleEnum, Response, Workflow,
 };
 
 #[allow(dead_code)]
@@ -256,7 +269,197 @@ pub fn correct_analysis_resource_id(message: &mut Message) {
     }
 }
 
-pub fn add_support_graphs(response: &mut Response, query_graph: &QueryGraph, cqs_query: &Box<dyn template::CQSTemplate>, query_template: &QueryTemplate) {
+/// sha256 of the rendered template (attribute constraints & cqs block included) & its scoring function
+pub fn compute_query_hash(cqs_query: &Box<dyn template::CQSTemplate>, query_template: &QueryTemplate) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(serde_json::to_vec(query_template).expect("Could not serialize query template"));
+    hasher.update(cqs_query.scoring_function().as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+pub fn build_provenance_attributes(cqs_query: &Box<dyn template::CQSTemplate>, query_template: &QueryTemplate, query_hash: &str) -> Vec<Attribute> {
+    // without a version, any edit to the template file changes the one reported
+    let template_version = query_template.cqs.version.clone().unwrap_or_else(|| format!("sha256:{}", &cqs_query.template_hash()[..12]));
+    vec![
+        ("cqs_template_name", cqs_query.name()),
+        ("cqs_template_version", template_version),
+        ("cqs_query_hash", query_hash.to_string()),
+    ]
+    .into_iter()
+    .map(|(name, value)| {
+        let mut attribute = Attribute::new("biolink:Attribute".to_string(), serde_json::Value::from(value));
+        attribute.original_attribute_name = Some(name.to_string());
+        attribute.attribute_source = Some(CQS_INFORES.clone());
+        attribute
+    })
+    .collect()
+}
+
+// biolink:max_research_phase values, lowest first
+const RESEARCH_PHASE_ORDER: [&str; 7] = [
+    "not_provided",
+    "pre_clinical_research_phase",
+    "clinical_trial_phase",
+    "clinical_trial_phase_1",
+    "clinical_trial_phase_2",
+    "clinical_trial_phase_3",
+    "clinical_trial_phase_4",
+];
+
+pub fn aggregate_attribute_values(aggregation: &AttributeAggregation, values: Vec<Value>) -> Option<Value> {
+    let values: Vec<Value> = values
+        .into_iter()
+        .flat_map(|v| match v {
+            Value::Array(inner) => inner,
+            Value::Null => vec![],
+            _ => vec![v],
+        })
+        .collect();
+
+    if values.is_empty() {
+        return None;
+    }
+
+    let as_number = |v: &Value| match v {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.parse::<f64>().ok(),
+        _ => None,
+    };
+    let as_research_phase = |v: &Value| v.as_str().and_then(|s| RESEARCH_PHASE_ORDER.iter().position(|phase| *phase == s));
+    let all_numeric = values.iter().all(|v| as_number(v).is_some());
+    let all_research_phases = values.iter().all(|v| as_research_phase(v).is_some());
+    let compare = |a: &&Value, b: &&Value| {
+        if all_numeric {
+            as_number(*a).partial_cmp(&as_number(*b)).unwrap_or(Ordering::Equal)
+        } else if all_research_phases {
+            as_research_phase(*a).cmp(&as_research_phase(*b))
+        } else {
+            a.to_string().cmp(&b.to_string())
+        }
+    };
+
+    match aggregation {
+        AttributeAggregation::First => values.first().cloned(),
+        AttributeAggregation::Max => values.iter().max_by(compare).cloned(),
+        AttributeAggregation::Min => values.iter().min_by(compare).cloned(),
+        AttributeAggregation::List => Some(Value::Array(values.into_iter().unique_by(|v| v.to_string()).collect())),
+        AttributeAggregation::Count => Some(Value::from(values.len())),
+    }
+}
+
+pub fn lift_support_path_attributes(attribute_rules: &Vec<AttributeRule>, support_edge_ids: &Vec<String>, edges: &HashMap<String, Edge>) -> Vec<Attribute> {
+    attribute_rules
+        .iter()
+        .filter_map(|rule| {
+            let values: Vec<Value> = support_edge_ids
+                .iter()
+                .filter_map(|edge_id| edges.get(edge_id))
+                .filter_map(|edge| edge.attributes.as_ref())
+                .flatten()
+                .filter(|a| a.attribute_type_id == rule.attribute_type_id)
+                .map(|a| a.value.clone())
+                .collect();
+
+            aggregate_attribute_values(&rule.aggregation, values).map(|value| {
+                let output_attribute_type_id = rule.output_attribute_type_id.clone().unwrap_or(rule.attribute_type_id.clone());
+                let mut attribute = Attribute::new(output_attribute_type_id, value);
+                attribute.original_attribute_name = Some(format!("{}({})", rule.aggregation, rule.attribute_type_id));
+                attribute.attribute_source = Some(CQS_INFORES.clone());
+                attribute
+            })
+        })
+        .collect()
+}
+
+lazy_static! {
+    static ref EXPLANATION_PLACEHOLDER: regex::Regex = regex::Regex::new(r"\{([A-Za-z0-9_]+)(?:\.([A-Za-z0-9_:]+))?\}").unwrap();
+}
+
+// eg, 'biolink:physically_interacts_with' -> 'physically interacts with'
+fn biolink_label(curie: &str) -> String {
+    curie.trim_start_matches("biolink:").replace('_', " ")
+}
+
+fn explanation_value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Array(values) => values.iter().map(explanation_value_to_string).join(", "),
+        _ => value.to_string(),
+    }
+}
+
+/// fills '{n0}', '{n0.id}' & '{e0.<predicate|subject|object|primary_knowledge_source|attribute>}' from a result's bindings
+pub fn render_explanation(
+    pattern: &str,
+    node_bindings: &BTreeMap<String, Vec<NodeBinding>>,
+    edge_bindings: &BTreeMap<String, Vec<EdgeBinding>>,
+    knowledge_graph: &KnowledgeGraph,
+) -> String {
+    EXPLANATION_PLACEHOLDER
+        .replace_all(pattern, |caps: &regex::Captures| {
+            let key = &caps[1];
+            let field = caps.get(2).map(|m| m.as_str());
+
+            if let Some(node_id) = node_bindings.get(key).and_then(|nb| nb.first()).map(|nb| nb.id.to_string()) {
+                return match field {
+                    Some("id") => node_id,
+                    _ => knowledge_graph.nodes.get(&node_id).and_then(|n| n.name.clone()).unwrap_or(node_id),
+                };
+            }
+
+            if let Some(edge) = edge_bindings.get(key).and_then(|eb| eb.first()).and_then(|eb| knowledge_graph.edges.get(&eb.id)) {
+                let value = match field.unwrap_or("predicate") {
+                    "predicate" => Some(biolink_label(&edge.predicate.to_string())),
+                    "subject" => Some(edge.subject.to_string()),
+                    "object" => Some(edge.object.to_string()),
+                    "primary_knowledge_source" => edge
+                        .sources
+                        .iter()
+                        .find(|source| source.resource_role.eq(&ResourceRoleEnum::PrimaryKnowledgeSource))
+                        .map(|source| source.resource_id.to_string()),
+                    attribute_field => edge.attributes.as_ref().and_then(|attributes| {
+                        attributes
+                            .iter()
+                            .find(|a| a.attribute_type_id == attribute_field || a.attribute_type_id == format!("biolink:{}", attribute_field))
+                            .map(|a| explanation_value_to_string(&a.value))
+                    }),
+                };
+                return value.unwrap_or("unknown".to_string());
+            }
+
+            "unknown".to_string()
+        })
+        .to_string()
+}
+
+pub fn bind_intermediate_nodes(
+    intermediate_nodes: &Vec<IntermediateNode>,
+    template_node_bindings: &BTreeMap<String, Vec<NodeBinding>>,
+    new_node_bindings: &mut BTreeMap<String, Vec<NodeBinding>>,
+) -> Vec<Attribute> {
+    let mut auxiliary_graph_attributes = vec![];
+    intermediate_nodes.iter().for_each(|intermediate_node| {
+        if let Some(node_binding_value) = template_node_bindings.get(&intermediate_node.template_node_id) {
+            let binding_id = intermediate_node.binding_id.clone().unwrap_or(intermediate_node.template_node_id.clone());
+            match intermediate_node.mode {
+                IntermediateNodeMode::NodeBinding => {
+                    new_node_bindings.insert(binding_id, node_binding_value.to_vec());
+                }
+                IntermediateNodeMode::AuxiliaryGraph => {
+                    let ids: Vec<String> = node_binding_value.iter().map(|nb| nb.id.to_string()).collect();
+                    let mut attribute = Attribute::new("biolink:Attribute".to_string(), serde_json::Value::from(ids));
+                    attribute.original_attribute_name = Some(binding_id);
+                    attribute.attribute_source = Some(CQS_INFORES.clone());
+                    auxiliary_graph_attributes.push(attribute);
+                }
+            }
+        }
+    });
+    auxiliary_graph_attributes
+}
+
+pub fn add_support_graphs(response: &mut Response, query_graph: &QueryGraph, cqs_query: &Box<dyn template::CQSTemplate>, query_template: &QueryTemplate, query_hash: &str) {
+    let provenance_attributes = build_provenance_attributes(cqs_query, query_template, query_hash);
     let mut auxiliary_graphs: BTreeMap<String, AuxiliaryGraph> = BTreeMap::new();
 
     let query_graph_edge_entry = query_graph.edges.iter().next().expect("Could not get QG edge");
@@ -278,6 +481,11 @@ pub fn add_support_graphs(response: &mut Response, query_graph: &QueryGraph, cqs
                 new_node_bindings.insert(query_edge_subject_id.clone(), drug_node_binding_value.to_vec());
             }
 
+            let auxiliary_graph_attributes = match &query_template.cqs.intermediate_nodes {
+                Some(intermediate_nodes) => bind_intermediate_nodes(intermediate_nodes, &result.node_bindings, &mut new_node_bindings),
+                None => vec![],
+            };
+
             let mut local_auxiliary_graphs: BTreeMap<String, AuxiliaryGraph> = BTreeMap::new();
             result.analyses.iter().for_each(|analysis| {
                 let eb_ids: Vec<String> = analysis
@@ -286,7 +494,10 @@ pub fn add_support_graphs(response: &mut Response, query_graph: &QueryGraph, cqs
                     .map(|(_k, v)| v.iter().map(|eb| eb.id.clone()).collect::<Vec<String>>())
                     .flatten()
                     .collect();
-                let ag = AuxiliaryGraph::new(eb_ids);
+                let mut ag = AuxiliaryGraph::new(eb_ids);
+                if !auxiliary_graph_attributes.is_empty() {
+                    ag.attributes.get_or_insert(vec![]).extend(auxiliary_graph_attributes.clone());
+                }
                 let auxiliary_graph_id = uuid::Uuid::new_v4().to_string();
                 local_auxiliary_graphs.insert(auxiliary_graph_id, ag);
             });
@@ -314,6 +525,7 @@ pub fn add_support_graphs(response: &mut Response, query_graph: &QueryGraph, cqs
                         knowledge_level_attribute.attribute_source = Some(CQS_INFORES.clone());
 
                         let mut new_edge_attributes = vec![support_graphs_attribute, agent_type_attribute, knowledge_level_attribute];
+                        new_edge_attributes.extend(provenance_attributes.clone());
 
                         if let Some(attribute_type_ids) = &query_template.cqs.attribute_type_ids {
                             if let Some(kg) = &mut response.message.knowledge_graph {
@@ -332,6 +544,25 @@ pub fn add_support_graphs(response: &mut Response, query_graph: &QueryGraph, cqs
                             }
                         }
 
+                        if let (Some(attribute_rules), Some(kg)) = (&query_template.cqs.attribute_rules, &response.message.knowledge_graph) {
+                            let support_edge_ids: Vec<String> = local_auxiliary_graphs.values().flat_map(|ag| ag.edges.clone()).unique().collect();
+                            new_edge_attributes.extend(lift_support_path_attributes(attribute_rules, &support_edge_ids, &kg.edges));
+                        }
+
+                        if let (Some(explanation), Some(kg)) = (&query_template.cqs.explanation, &response.message.knowledge_graph) {
+                            let mut template_edge_bindings: BTreeMap<String, Vec<EdgeBinding>> = BTreeMap::new();
+                            result.analyses.iter().for_each(|analysis| {
+                                analysis.edge_bindings.iter().for_each(|(k, v)| {
+                                    template_edge_bindings.entry(k.clone()).or_insert(v.clone());
+                                })
+                            });
+                            let description = render_explanation(explanation, &result.node_bindings, &template_edge_bindings, kg);
+                            let mut description_attribute = Attribute::new("biolink:description".to_string(), serde_json::Value::from(description));
+                            description_attribute.original_attribute_name = Some("cqs_explanation".to_string());
+                            description_attribute.attribute_source = Some(CQS_INFORES.clone());
+                            new_edge_attributes.push(description_attribute);
+                        }
+
                         new_edge.attributes = Some(new_edge_attributes);
                         // println!("new_edge: {:?}", new_edge);
                         if let Some(kg) = &mut response.message.knowledge_graph {
@@ -343,6 +574,7 @@ pub fn add_support_graphs(response: &mut Response, query_graph: &QueryGraph, cqs
                                 analysis
                                     .edge_bindings
                                     .insert(query_graph_edge_entry.0.clone(), vec![EdgeBinding::new(new_kg_edge_id.clone())]);
+                                analysis.attributes.get_or_insert(vec![]).extend(provenance_attributes.clone());
                             });
                         }
                     }
@@ -383,13 +615,60 @@ pub fn compute_composite_score(entry_values: Vec<CQSCompositeScoreValue>) -> f64
     }
 }
 
-pub async fn send_to_wfr(cqs_query: &Box<dyn template::CQSTemplate>, query: &trapi_model_rs::Query) -> Option<Response> {
+#[derive(Clone, Default)]
+pub struct ProgressLog {
+    job_id: Option<i32>,
+    entries: Arc<Mutex<Vec<LogEntry>>>,
+}
+
+impl ProgressLog {
+    pub fn new(job_id: Option<i32>) -> ProgressLog {
+        ProgressLog {
+            job_id,
+            entries: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    pub async fn log(&self, level: &str, code: Option<&str>, message: String) {
+        let date_logged = Utc::now().naive_utc();
+        let code = code.map(|c| c.to_string());
+        if let Some(entry) = build_log_entry(&date_logged, level, &code, &message) {
+            self.entries.lock().expect("Could not lock progress log").push(entry);
+        }
+        if let Some(job_id) = self.job_id {
+            if let Err(e) = JOB_STORE.insert_log(&NewJobLog::new(job_id, date_logged, level.to_string(), code, message)).await {
+                warn!("Could not record log of job {}: {}", job_id, e);
+            }
+        }
+    }
+
+    pub async fn info(&self, code: Option<&str>, message: String) {
+        self.log("INFO", code, message).await;
+    }
+
+    pub async fn warning(&self, code: Option<&str>, message: String) {
+        self.log("WARNING", code, message).await;
+    }
+
+    pub async fn error(&self, code: Option<&str>, message: String) {
+        self.log("ERROR", code, message).await;
+    }
+
+    pub fn entries(&self) -> Vec<LogEntry> {
+        self.entries.lock().expect("Could not lock progress log").clone()
+    }
+}
+
+pub struct WorkflowRunnerUrl(pub String);
+
+pub fn workflow_runner_url() -> String {
+    env::var("WORKFLOW_RUNNER_URL").unwrap_or("https://translator-workflow-runner.renci.org".to_string())
+}
+
+pub async fn send_to_wfr(cqs_query: &Box<dyn template::CQSTemplate>, query: &trapi_model_rs::Query, workflow_runner_url: &str, progress_log: &ProgressLog) -> Option<Response> {
     let request_client = REQWEST_CLIENT.get().await;
 
-    let workflow_runner_url = format!(
-        "{}/query",
-        env::var("WORKFLOW_RUNNER_URL").unwrap_or("https://translator-workflow-runner.renci.org".to_string())
-    );
+    let workflow_runner_url = format!("{}/query", workflow_runner_url);
 
     let backoff_multiplier = 2;
     let retries = 3;
@@ -402,42 +681,125 @@ pub async fn send_to_wfr(cqs_query: &Box<dyn template::CQSTemplate>, query: &tra
         let wfr_response: Option<Response> = match wfr_response_result {
             Ok(response) => {
                 info!("WFR response.status(): {} for query {} ", response.status(), cqs_query.name());
+                progress_log
+                    .info(
+                        Some("wfr_status"),
+                        format!("{}: WFR responded with {} on attempt {}", cqs_query.name(), response.status(), attempt),
+                    )
+                    .await;
                 let result_data = response.json::<trapi_model_rs::Response>().await;
                 match result_data {
                     Ok(data) => Some(data),
                     Err(e) => {
                         warn!("Error reading response from WFR: {}", e);
+                        progress_log
+                            .error(Some("wfr_error"), format!("{}: Error reading response from WFR: {}", cqs_query.name(), e))
+                            .await;
                         None
                     }
                 }
             }
             Err(e) => {
                 warn!("Failed to send query to WFR: {}", e);
+                progress_log
+                    .error(Some("wfr_error"), format!("{}: Failed to send query to WFR: {}", cqs_query.name(), e))
+                    .await;
                 None
             }
         };
         if let Some(r) = wfr_response {
             trapi_response = Some(r);
             break;
-        } else {
+        } else if attempt < retries {
             let retry_backoff_sleep_duration = attempt * backoff_multiplier * 15;
             debug!("retry_backoff_sleep_duration: {}", retry_backoff_sleep_duration);
+            progress_log
+                .warning(Some("wfr_retry"), format!("{}: retrying in {} seconds", cqs_query.name(), retry_backoff_sleep_duration))
+                .await;
             tokio::time::sleep(Duration::from_secs(retry_backoff_sleep_duration)).await;
         }
     }
     trapi_response
 }
 
-pub async fn process(query_graph: &QueryGraph, cqs_query: &Box<dyn template::CQSTemplate>, ids: &Vec<trapi_model_rs::CURIE>) -> Option<Response> {
-    let mut query_template: QueryTemplate = cqs_query.render_query_template(ids.clone());
+/// 0 disables the cache, which needs the postgres job store
+pub fn wfr_cache_ttl() -> i64 {
+    if job_store_kind() != JobStoreKind::Postgres {
+        return 0;
+    }
+    env::var("WFR_CACHE_TTL").ok().and_then(|ttl| ttl.parse::<i64>().ok()).unwrap_or(3600)
+}
 
-    let attribute_constraint = query_template.first_edge_attribute_constraint();
+pub async fn send_to_wfr_with_cache(
+    cqs_query: &Box<dyn template::CQSTemplate>,
+    query: &trapi_model_rs::Query,
+    query_hash: &str,
+    workflow_runner_url: &str,
+    progress_log: &ProgressLog,
+) -> Option<Response> {
+    let ttl = wfr_cache_ttl();
+    let use_cache = ttl > 0 && !query.bypass_cache.unwrap_or(false);
+
+    if use_cache {
+        match cache_actions::find_fresh(query_hash, ttl).await {
+            Ok(Some(entry)) => match serde_json::from_slice::<Response>(entry.response.as_slice()) {
+                Ok(cached_response) => {
+                    info!("using cached WFR response for query {} ({})", cqs_query.name(), query_hash);
+                    progress_log.info(Some("wfr_cache_hit"), format!("{}: using cached WFR response", cqs_query.name())).await;
+                    return Some(cached_response);
+                }
+                Err(e) => warn!("Could not deserialize cached WFR response: {}", e),
+            },
+            Ok(None) => {}
+            Err(e) => warn!("Could not look up cached WFR response: {}", e),
+        }
+    }
 
-    query_template.remove_edge_attribute_constraints();
-    let query = query_template.to_query();
-    info!("cqs_query {} being sent to WFR: {}", cqs_query.name(), serde_json::to_string(&query).unwrap());
+    let trapi_response = send_to_wfr(cqs_query, query, workflow_runner_url, progress_log).await;
+
+    if ttl > 0 {
+        if let Some(tr) = &trapi_response {
+            let entry = WFRCacheEntry::new(query_hash.to_string(), serde_json::to_vec(tr).expect("Could not serialize response"));
+            if let Err(e) = cache_actions::upsert(&entry).await {
+                warn!("Could not cache WFR response: {}", e);
+            }
+        }
+    }
+
+    trapi_response
+}
+
+pub async fn delete_expired_wfr_cache_entries() {
+    debug!("deleting expired wfr cache entries");
+    let ttl = wfr_cache_ttl();
+    if ttl > 0 {
+        if let Err(e) = cache_actions::delete_expired(ttl).await {
+            warn!("Could not delete expired WFR cache entries: {}", e);
+        }
+    }
+}
 
-    if let Some(mut tr) = send_to_wfr(cqs_query, &query).await {
+pub async fn process(
+    query_graph: &QueryGraph,
+    cqs_query: &Box<dyn template::CQSTemplate>,
+    ids: &Vec<trapi_model_rs::CURIE>,
+    bypass_cache: bool,
+    workflow_runner_url: &str,
+    progress_log: &ProgressLog,
+) -> Option<Response> {
+    let (query_template, mut query, attribute_constraint, query_hash) = render_template_query(cqs_query, ids);
+    if bypass_cache {
+        query.bypass_cache = Some(true);
+    }
+    info!(
+        "cqs_query {} ({}) being sent to WFR: {}",
+        cqs_query.name(),
+        query_hash,
+        serde_json::to_string(&query).unwrap()
+    );
+    progress_log.info(Some("template_started"), format!("{}: started ({})", cqs_query.name(), query_hash)).await;
+
+    if let Some(mut tr) = send_to_wfr_with_cache(cqs_query, &query, &query_hash, workflow_runner_url, progress_log).await {
         let uuid = uuid::Uuid::new_v4().to_string();
         write_wfr_response("pre", &tr, &uuid, &cqs_query.name());
 
@@ -461,11 +823,25 @@ pub async fn process(query_graph: &QueryGraph, cqs_query: &Box<dyn template::CQS
                         })
                         .for_each(|_a| results_to_remove.push(result.clone()));
                 }
+                let results_before = results.len();
                 results.retain(|r| !results_to_remove.contains(r));
+                progress_log
+                    .info(
+                        Some("attribute_constraint"),
+                        format!(
+                            "{}: {} removed {} edges, kept {} of {} results",
+                            cqs_query.name(),
+                            describe_attribute_constraint(&ac),
+                            edge_keys_to_remove.len(),
+                            results.len(),
+                            results_before
+                        ),
+                    )
+                    .await;
             }
         }
 
-        add_support_graphs(&mut tr, query_graph, cqs_query, &query_template);
+        add_support_graphs(&mut tr, query_graph, cqs_query, &query_template, &query_hash);
 
         sort_analysis_by_score(&mut tr.message);
         sort_results_by_analysis_score(&mut tr.message);
@@ -516,9 +892,15 @@ pub async fn process(query_graph: &QueryGraph, cqs_query: &Box<dyn template::CQS
 
         write_wfr_response("post", &tr, &uuid, &cqs_query.name());
 
+        let num_results = tr.message.results.as_ref().map(|results| results.len()).unwrap_or(0);
+        progress_log
+            .info(Some("template_finished"), format!("{}: finished with {} results", cqs_query.name(), num_results))
+            .await;
+
         return Some(tr);
     }
 
+    progress_log.error(Some("template_failed"), format!("{}: no response from WFR", cqs_query.name())).await;
     None
     // let node_binding_to_log_odds_map = util::build_node_binding_to_log_odds_data_map(canned_query_response.message.clone());
     // let trapi_response = util::add_composite_score_attributes(canned_query_response, node_binding_to_log_odds_map, &cqs_query);
@@ -536,31 +918,358 @@ fn write_wfr_response(suffix: &str, trapi_response: &Response, uuid: &str, cqs_q
     }
 }
 
-pub async fn get_responses_from_job(query: &AsyncQuery) -> Vec<trapi_model_rs::Response> {
-    let mut responses: Vec<trapi_model_rs::Response> = vec![];
+pub struct TemplateRun {
+    pub name: String,
+    pub outcome: TemplateOutcome,
+    pub response: Option<Response>,
+}
 
-    if let Some(query_graph) = &query.message.query_graph {
-        if let Some((_edge_key, edge_value)) = &query_graph.edges.iter().find(|(_k, v)| {
-            if let (Some(predicates), Some(knowledge_type)) = (&v.predicates, &v.knowledge_type) {
-                if predicates.contains(&"biolink:treats".to_string()) && knowledge_type == &KnowledgeType::INFERRED {
-                    return true;
-                }
+pub fn template_timeout_seconds() -> u64 {
+    env::var("TEMPLATE_TIMEOUT_SECONDS")
+        .ok()
+        .and_then(|template_timeout| template_timeout.parse::<u64>().ok())
+        .unwrap_or(400)
+}
+
+pub async fn run_template(
+    query_graph: &QueryGraph,
+    cqs_query: &Box<dyn template::CQSTemplate>,
+    ids: &Vec<trapi_model_rs::CURIE>,
+    bypass_cache: bool,
+    workflow_runner_url: &str,
+    progress_log: &ProgressLog,
+) -> TemplateRun {
+    let template_timeout = Duration::from_secs(template_timeout_seconds());
+    let (outcome, response) = match timeout(template_timeout, process(query_graph, cqs_query, ids, bypass_cache, workflow_runner_url, progress_log)).await {
+        Ok(Some(response)) => (TemplateOutcome::Succeeded, Some(response)),
+        Ok(None) => (TemplateOutcome::Failed, None),
+        Err(_) => {
+            warn!("cqs_query {} timed out after {} seconds", cqs_query.name(), template_timeout.as_secs());
+            progress_log
+                .error(
+                    Some("template_timed_out"),
+                    format!("{}: timed out after {} seconds", cqs_query.name(), template_timeout.as_secs()),
+                )
+                .await;
+            (TemplateOutcome::TimedOut, None)
+        }
+    };
+    TemplateRun {
+        name: cqs_query.name(),
+        outcome,
+        response,
+    }
+}
+
+pub async fn run_templates(
+    message: &Message,
+    cqs_templates: &Vec<&'static Box<dyn template::CQSTemplate>>,
+    bypass_cache: bool,
+    workflow_runner_url: &str,
+    progress_log: &ProgressLog,
+) -> Vec<TemplateRun> {
+    let (Some(query_graph), Ok(ids)) = (&message.query_graph, treats_query_ids(message)) else {
+        return vec![];
+    };
+    let future_template_runs: Vec<_> = cqs_templates
+        .iter()
+        .map(|cqs_query| run_template(&query_graph, cqs_query, &ids, bypass_cache, workflow_runner_url, progress_log))
+        .collect();
+    join_all(future_template_runs).await
+}
+
+/// the attribute constraint is stripped from the query & applied to the WFR response instead
+pub fn render_template_query(cqs_query: &Box<dyn template::CQSTemplate>, ids: &Vec<trapi_model_rs::CURIE>) -> (QueryTemplate, Query, Option<AttributeConstraint>, String) {
+    let mut query_template: QueryTemplate = cqs_query.render_query_template(ids.clone());
+    let query_hash = compute_query_hash(cqs_query, &query_template);
+    let attribute_constraint = query_template.first_edge_attribute_constraint();
+    query_template.remove_edge_attribute_constraints();
+    let query = query_template.to_query();
+    (query_template, query, attribute_constraint, query_hash)
+}
+
+pub fn dry_run_templates(message: &Message, cqs_templates: &Vec<&'static Box<dyn template::CQSTemplate>>, bypass_cache: bool) -> Vec<TemplateDryRun> {
+    let Ok(ids) = treats_query_ids(message) else {
+        return vec![];
+    };
+    cqs_templates
+        .iter()
+        .map(|cqs_query| {
+            let (_query_template, mut query, attribute_constraint, query_hash) = render_template_query(cqs_query, ids);
+            if bypass_cache {
+                query.bypass_cache = Some(true);
             }
-            return false;
-        }) {
-            if let Some((_node_key, node_value)) = &query_graph.nodes.iter().find(|(k, _v)| *k == &edge_value.object) {
-                if let Some(ids) = &node_value.ids {
-                    let future_responses: Vec<_> = WHITELISTED_TEMPLATE_QUERIES.iter().map(|cqs_query| util::process(&query_graph, cqs_query, &ids)).collect();
-                    let joined_future_responses = join_all(future_responses).await;
-                    joined_future_responses
-                        .into_iter()
-                        .filter_map(std::convert::identity)
-                        .for_each(|trapi_response| responses.push(trapi_response));
-                }
+            TemplateDryRun {
+                name: cqs_query.name(),
+                query_hash,
+                query,
+                attribute_constraint,
             }
+        })
+        .collect()
+}
+
+pub fn parse_template_names(template_names: Option<&str>) -> Option<Vec<String>> {
+    let template_names = template_names?
+        .split(',')
+        .map(|name| name.trim())
+        .filter(|name| !name.is_empty())
+        .map(|name| name.to_string())
+        .collect_vec();
+    Some(template_names).filter(|names| !names.is_empty())
+}
+
+pub fn select_templates(template_names: Option<&Vec<String>>) -> Result<Vec<&'static Box<dyn template::CQSTemplate>>, CQSError> {
+    let Some(template_names) = template_names else {
+        return Ok(WHITELISTED_TEMPLATE_QUERIES.iter().collect());
+    };
+    let unknown_names = template_names.iter().filter(|name| find_template(name).is_err()).collect_vec();
+    if !unknown_names.is_empty() {
+        let known_names = WHITELISTED_TEMPLATE_QUERIES.iter().map(|cqs_template| cqs_template.name()).join(", ");
+        return Err(CQSError::InvalidQuery(format!(
+            "unknown templates: {}, expected some of: {}",
+            unknown_names.iter().join(", "),
+            known_names
+        )));
+    }
+    Ok(WHITELISTED_TEMPLATE_QUERIES
+        .iter()
+        .filter(|cqs_template| template_names.contains(&cqs_template.name()))
+        .collect())
+}
+
+pub const SUPPORTED_QUERY_SHAPE: &str =
+    "CQS answers query graphs with an edge whose predicates include biolink:treats, whose knowledge_type is 'inferred' & whose object node has ids";
+
+pub fn treats_query_ids(message: &Message) -> Result<&Vec<trapi_model_rs::CURIE>, CQSError> {
+    let query_graph = message
+        .query_graph
+        .as_ref()
+        .ok_or_else(|| CQSError::InvalidQuery("message has no query_graph".to_string()))?;
+    let treats_edge = query_graph
+        .edges
+        .values()
+        .find(|v| match (&v.predicates, &v.knowledge_type) {
+            (Some(predicates), Some(knowledge_type)) => predicates.contains(&"biolink:treats".to_string()) && knowledge_type == &KnowledgeType::INFERRED,
+            _ => false,
+        })
+        .ok_or_else(|| CQSError::UnsupportedQuery(format!("query_graph has no inferred biolink:treats edge. {}", SUPPORTED_QUERY_SHAPE)))?;
+    let object_node = query_graph
+        .nodes
+        .get(&treats_edge.object)
+        .ok_or_else(|| CQSError::InvalidQuery(format!("edge object '{}' is not a node of the query_graph", treats_edge.object)))?;
+    match &object_node.ids {
+        Some(ids) if !ids.is_empty() => Ok(ids),
+        _ => Err(CQSError::UnsupportedQuery(format!(
+            "object node '{}' of the treats edge has no ids. {}",
+            treats_edge.object, SUPPORTED_QUERY_SHAPE
+        ))),
+    }
+}
+
+fn template_node_categories(query_template: &QueryTemplate, node_id: &str, default: &str) -> Vec<String> {
+    query_template
+        .message
+        .query_graph
+        .as_ref()
+        .and_then(|qg| qg.nodes.get(node_id))
+        .and_then(|node| node.categories.clone())
+        .filter(|categories| !categories.is_empty())
+        .unwrap_or(vec![default.to_string()])
+}
+
+const CHEMICAL_ID_PREFIXES: &[&str] = &["PUBCHEM.COMPOUND", "CHEMBL.COMPOUND", "UNII", "CHEBI", "DRUGBANK", "MESH", "RXCUI"];
+const DISEASE_ID_PREFIXES: &[&str] = &["MONDO", "DOID", "OMIM", "ORPHANET", "EFO", "UMLS", "MESH", "HP", "NCIT"];
+
+// used unless a template lists its own under cqs.id_prefixes
+const DEFAULT_ID_PREFIXES: [(&str, &[&str]); 6] = [
+    ("biolink:ChemicalEntity", CHEMICAL_ID_PREFIXES),
+    ("biolink:Drug", CHEMICAL_ID_PREFIXES),
+    ("biolink:SmallMolecule", CHEMICAL_ID_PREFIXES),
+    ("biolink:DiseaseOrPhenotypicFeature", DISEASE_ID_PREFIXES),
+    ("biolink:Disease", DISEASE_ID_PREFIXES),
+    ("biolink:PhenotypicFeature", DISEASE_ID_PREFIXES),
+];
+
+fn template_node_id_prefixes(query_template: &QueryTemplate, node_id: &str, category: &str) -> Vec<String> {
+    match query_template.cqs.id_prefixes.as_ref().and_then(|id_prefixes| id_prefixes.get(node_id)) {
+        Some(id_prefixes) => id_prefixes.clone(),
+        None => DEFAULT_ID_PREFIXES
+            .iter()
+            .find(|(default_category, _)| *default_category == category)
+            .map(|(_, id_prefixes)| id_prefixes.iter().map(|prefix| prefix.to_string()).collect())
+            .unwrap_or_default(),
+    }
+}
+
+pub fn build_meta_knowledge_graph(cqs_templates: &Vec<Box<dyn template::CQSTemplate>>) -> Result<MetaKnowledgeGraph, CQSError> {
+    let mut attribute_type_ids_by_edge: BTreeMap<(String, String), Vec<String>> = BTreeMap::new();
+    let mut id_prefixes_by_category: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    cqs_templates.iter().for_each(|cqs_template| {
+        let query_template = cqs_template.render_query_template(vec![]);
+        let attribute_type_ids = query_template.cqs.attribute_type_ids.clone().unwrap_or_default();
+        let drug_categories = template_node_categories(&query_template, &cqs_template.template_drug_node_id(), "biolink:ChemicalEntity");
+        let disease_categories = template_node_categories(&query_template, &cqs_template.template_disease_node_id(), "biolink:DiseaseOrPhenotypicFeature");
+        drug_categories.iter().cartesian_product(disease_categories.iter()).for_each(|(subject, object)| {
+            let edge_attribute_type_ids = attribute_type_ids_by_edge.entry((subject.clone(), object.clone())).or_default();
+            edge_attribute_type_ids.extend(attribute_type_ids.iter().cloned());
+        });
+        for (node_id, categories) in [
+            (cqs_template.template_drug_node_id(), drug_categories),
+            (cqs_template.template_disease_node_id(), disease_categories),
+        ] {
+            categories.into_iter().for_each(|category| {
+                let id_prefixes = template_node_id_prefixes(&query_template, &node_id, &category);
+                id_prefixes_by_category.entry(category).or_default().extend(id_prefixes);
+            });
         }
+    });
+
+    let nodes: BTreeMap<String, Value> = id_prefixes_by_category
+        .into_iter()
+        .map(|(category, id_prefixes)| (category, json!({ "id_prefixes": id_prefixes.into_iter().unique().collect_vec() })))
+        .collect();
+
+    let edges = attribute_type_ids_by_edge
+        .into_iter()
+        .map(|((subject, object), attribute_type_ids)| {
+            let attributes = attribute_type_ids
+                .into_iter()
+                .unique()
+                .map(|attribute_type_id| json!({ "attribute_type_id": attribute_type_id }))
+                .collect_vec();
+            json!({
+                "subject": subject,
+                "predicate": "biolink:treats",
+                "object": object,
+                "knowledge_types": ["inferred"],
+                "attributes": attributes,
+            })
+        })
+        .collect_vec();
+
+    Ok(serde_json::from_value(json!({ "nodes": nodes, "edges": edges }))?)
+}
+
+/// stands in for the disease ids pinned from the submitted query
+pub const PINNED_IDS_PLACEHOLDER: &str = "{{disease_ids}}";
+
+pub fn find_template(name: &str) -> Result<&'static Box<dyn template::CQSTemplate>, CQSError> {
+    WHITELISTED_TEMPLATE_QUERIES
+        .iter()
+        .find(|cqs_template| cqs_template.name() == name)
+        .ok_or_else(|| CQSError::TemplateNotFound(name.to_string()))
+}
+
+fn is_readme_heading(line: &str) -> bool {
+    line.starts_with('#') || (line.len() > 4 && line.starts_with("**") && line.ends_with("**"))
+}
+
+pub fn readme_summary(readme: &str) -> Option<String> {
+    let summary = readme
+        .lines()
+        .map(|line| line.trim())
+        .skip_while(|line| line.is_empty() || is_readme_heading(line))
+        .take_while(|line| !line.is_empty() && !is_readme_heading(line))
+        .join(" ");
+    Some(summary).filter(|summary| !summary.is_empty())
+}
+
+pub fn readme_pocs(readme: &str) -> Vec<String> {
+    let is_poc_heading = |line: &str| {
+        is_readme_heading(line)
+            && line
+                .split(|c: char| !c.is_alphanumeric())
+                .any(|word| ["poc", "pocs", "sme", "smes", "contact", "contacts"].contains(&word.to_lowercase().as_str()))
+    };
+    readme
+        .lines()
+        .map(|line| line.trim())
+        .skip_while(|line| !is_poc_heading(line))
+        .skip(1)
+        .take_while(|line| !is_readme_heading(line))
+        .filter_map(|line| line.strip_prefix("- ").or(line.strip_prefix("* ")))
+        .map(|poc| poc.trim().to_string())
+        .collect()
+}
+
+pub fn summarize_template(cqs_template: &Box<dyn template::CQSTemplate>) -> TemplateSummary {
+    let query_template = cqs_template.render_query_template(vec![trapi_model_rs::CURIE::from(PINNED_IDS_PLACEHOLDER)]);
+    let readme = cqs_template.readme().unwrap_or_default();
+
+    let predicates = query_template
+        .message
+        .query_graph
+        .iter()
+        .flat_map(|qg| qg.edges.values())
+        .filter_map(|edge| edge.predicates.clone())
+        .flatten()
+        .map(|predicate| predicate.to_string())
+        .unique()
+        .sorted()
+        .collect_vec();
+
+    // the runner parameters aren't typed, so the allowlists are read from the workflow as JSON
+    let workflow = serde_json::to_value(&query_template.workflow).unwrap_or_default();
+    let allowlist = workflow
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|operation| operation["runner_parameters"]["allowlist"].as_array())
+        .flatten()
+        .filter_map(|infores| infores.as_str().map(|i| i.to_string()))
+        .unique()
+        .collect_vec();
+
+    TemplateSummary {
+        name: cqs_template.name(),
+        summary: readme_summary(&readme),
+        poc: readme_pocs(&readme),
+        predicates,
+        allowlist,
+        edge_sources: query_template.cqs.edge_sources.clone(),
+        scoring_function: query_template.cqs.scoring_function.clone().unwrap_or(cqs_template.scoring_function()),
+        query: query_template.to_query(),
     }
-    responses
+}
+
+pub fn summarize_template_runs(template_runs: &Vec<TemplateRun>) -> (String, String) {
+    let names_by_outcome = |outcome: TemplateOutcome| template_runs.iter().filter(|tr| tr.outcome == outcome).map(|tr| tr.name.clone()).collect_vec();
+    let succeeded = names_by_outcome(TemplateOutcome::Succeeded);
+    let failed = names_by_outcome(TemplateOutcome::Failed);
+    let timed_out = names_by_outcome(TemplateOutcome::TimedOut);
+
+    let status = if failed.is_empty() && timed_out.is_empty() {
+        "Success"
+    } else if succeeded.is_empty() {
+        "Failed"
+    } else {
+        "PartialSuccess"
+    };
+
+    let description = [("succeeded", succeeded), ("failed", failed), ("timed out", timed_out)]
+        .into_iter()
+        .filter(|(_label, names)| !names.is_empty())
+        .map(|(label, names)| format!("{}: {}", label, names.join(", ")))
+        .join("; ");
+
+    (status.to_string(), description)
+}
+
+pub async fn merge_template_runs(message: Message, workflow: Option<Vec<Workflow>>, template_runs: Vec<TemplateRun>, progress_log: &ProgressLog) -> Response {
+    let (status, description) = summarize_template_runs(&template_runs);
+    if !description.is_empty() {
+        progress_log.info(Some("template_summary"), description.clone()).await;
+    }
+
+    let responses = template_runs.into_iter().filter_map(|tr| tr.response).collect_vec();
+    let mut res = merge_sort_truncate(message, workflow, responses).await;
+    res.status = Some(status);
+    if !description.is_empty() {
+        res.description = Some(description);
+    }
+    res.logs = Some(progress_log.entries());
+    res
 }
 
 pub async fn merge_sort_truncate(mut message: Message, workflow: Option<Vec<Workflow>>, responses: Vec<trapi_model_rs::Response>) -> trapi_model_rs::Response {
@@ -589,89 +1298,558 @@ pub async fn merge_sort_truncate(mut message: Message, workflow: Option<Vec<Work
     res
 }
 
-/// pulls undone jobs from a db & begins processing asynchronous submissions
-pub async fn process_asyncquery_jobs(undone_jobs: Vec<Job>) {
+pub fn job_lease_seconds() -> i64 {
+    env::var("JOB_LEASE_SECONDS").ok().and_then(|lease| lease.parse::<i64>().ok()).unwrap_or(300)
+}
+
+pub fn job_concurrency() -> usize {
+    env::var("JOB_CONCURRENCY")
+        .ok()
+        .and_then(|concurrency| concurrency.parse::<usize>().ok())
+        .unwrap_or(4)
+        .max(1)
+}
+
+pub fn job_timeout_seconds() -> u64 {
+    env::var("JOB_TIMEOUT_SECONDS").ok().and_then(|job_timeout| job_timeout.parse::<u64>().ok()).unwrap_or(450)
+}
+
+pub fn job_poll_seconds() -> u64 {
+    env::var("JOB_POLL_SECONDS").ok().and_then(|poll| poll.parse::<u64>().ok()).unwrap_or(60).max(1)
+}
+
+/// wakes the workers whenever a job is queued or cancelled on any replica
+pub async fn listen_for_job_notifications() {
+    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    loop {
+        match tokio_postgres::connect(&database_url, tokio_postgres::NoTls).await {
+            Ok((client, mut connection)) => {
+                let listener = tokio::task::spawn(async move {
+                    let mut messages = futures::stream::poll_fn(move |cx| connection.poll_message(cx));
+                    while let Some(message) = messages.next().await {
+                        match message {
+                            Ok(tokio_postgres::AsyncMessage::Notification(notification)) => match notification.channel() {
+                                "cqs_job_cancellations" => {
+                                    debug!("job cancelled notification: {}", notification.payload());
+                                    if let Ok(job_id) = notification.payload().parse::<i32>() {
+                                        signal_job_cancellation(job_id);
+                                    }
+                                }
+                                _ => {
+                                    debug!("job queued notification: {}", notification.payload());
+                                    JOB_WAKEUP.notify_one();
+                                }
+                            },
+                            Ok(_) => {}
+                            Err(e) => {
+                                warn!("job notification listener error: {}", e);
+                                break;
+                            }
+                        }
+                    }
+                });
+                match client.batch_execute("LISTEN cqs_jobs; LISTEN cqs_job_cancellations;").await {
+                    Ok(_) => {
+                        info!("listening for queued job notifications");
+                        let _ = listener.await;
+                    }
+                    Err(e) => {
+                        warn!("Could not LISTEN for job notifications: {}", e);
+                        listener.abort();
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("Could not connect to listen for job notifications: {}", e);
+            }
+        }
+        tokio::time::sleep(Duration::from_secs(30)).await;
+    }
+}
+
+lazy_static! {
+    static ref JOB_WORKERS: Arc<Semaphore> = Arc::new(Semaphore::new(job_concurrency()));
+    static ref JOB_CANCELLATIONS: Mutex<HashMap<i32, Arc<Notify>>> = Mutex::new(HashMap::new());
+}
+
+fn register_job_cancellation(job_id: i32) -> Arc<Notify> {
+    let cancellation = Arc::new(Notify::new());
+    JOB_CANCELLATIONS.lock().unwrap().insert(job_id, cancellation.clone());
+    cancellation
+}
+
+fn deregister_job_cancellation(job_id: i32) {
+    JOB_CANCELLATIONS.lock().unwrap().remove(&job_id);
+}
+
+fn signal_job_cancellation(job_id: i32) {
+    if let Some(cancellation) = JOB_CANCELLATIONS.lock().unwrap().get(&job_id) {
+        cancellation.notify_one();
+    }
+}
+
+/// claims undone jobs from a db & processes each asynchronous submission on its own task
+pub async fn process_asyncquery_jobs() {
     debug!("processing asyncquery jobs");
 
-    if undone_jobs.is_empty() {
-        return;
+    let lease_seconds = job_lease_seconds();
+    let job_timeout = Duration::from_secs(job_timeout_seconds());
+
+    loop {
+        let permit = JOB_WORKERS.clone().acquire_owned().await.expect("Job worker pool was closed");
+        match JOB_STORE.claim_next(&CQS_INSTANCE_ID, lease_seconds).await {
+            Ok(Some(job)) => {
+                tokio::task::spawn(async move {
+                    process_asyncquery_job(job, lease_seconds, job_timeout).await;
+                    drop(permit);
+                });
+            }
+            Ok(None) => break,
+            Err(e) => {
+                warn!("Could not claim next job: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+fn spawn_lease_heartbeat(job_id: i32, lease_seconds: i64, cancellation: Arc<Notify>) -> tokio::task::JoinHandle<()> {
+    tokio::task::spawn(async move {
+        let period = Duration::from_secs((lease_seconds / 3).max(1) as u64);
+        let mut interval_timer = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        loop {
+            interval_timer.tick().await;
+            match JOB_STORE.renew_lease(job_id, &CQS_INSTANCE_ID, lease_seconds).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    warn!("lost lease on job: {}", job_id);
+                    cancellation.notify_one();
+                    break;
+                }
+                // the lease may still be good, the reaper requeues the job if it was not renewed in time
+                Err(e) => warn!("Could not renew lease on job {}: {}", job_id, e),
+            }
+        }
+    })
+}
+
+pub async fn cancel_asyncquery_job(job: &Job) -> Result<Job, CQSError> {
+    let query = parse_job_query(job)?;
+    let progress_log = ProgressLog::new(Some(job.id));
+    let mut res = build_failure_response(&query, "Cancelled", "Cancelled by request".to_string());
+    if let Ok(job_logs) = JOB_STORE.find_logs_by_job_id(job.id).await {
+        res.logs = Some(job_logs.iter().filter_map(|job_log| job_log.to_log_entry()).collect());
     }
 
-    for mut job in undone_jobs.into_iter() {
-        info!("Processing Job: {}", job.id);
+    let Some(cancelled_job) = JOB_STORE.cancel(job.id, &encode_job_response(&res)).await? else {
+        return Err(CQSError::JobConflict(format!("Job {} has already finished", job.public_id)));
+    };
+    info!("Cancelled Job: {}", job.id);
+    progress_log.warning(Some("job_cancelled"), "Job cancelled by request".to_string()).await;
+    signal_job_cancellation(job.id);
+    enqueue_callback(job.id, &query.callback).await;
+    Ok(cancelled_job)
+}
 
-        job.date_started = Some(Utc::now().naive_utc());
-        job.status = JobStatus::Running;
-        job_actions::update(&job).await;
+pub fn gzip(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
 
-        let query: AsyncQuery = serde_json::from_slice(&job.query.as_slice()).expect("Could not deserialize AsyncQuery");
-        let responses = get_responses_from_job(&query).await;
+pub fn gunzip(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    flate2::read::GzDecoder::new(data).read_to_end(&mut decoded)?;
+    Ok(decoded)
+}
 
-        if responses.is_empty() {
+pub fn encode_job_response(res: &Response) -> JobResponse {
+    let json = serde_json::to_vec(res).expect("Could not serialize response");
+    let (kg_node_count, kg_edge_count) = match &res.message.knowledge_graph {
+        Some(kg) => (Some(kg.nodes.len() as i32), Some(kg.edges.len() as i32)),
+        None => (None, None),
+    };
+    JobResponse {
+        response: Some(gzip(json.as_slice()).expect("Could not compress response")),
+        response_encoding: Some("gzip".to_string()),
+        response_logs: res.logs.as_ref().and_then(|logs| serde_json::to_value(logs).ok()),
+        result_count: res.message.results.as_ref().map(|results| results.len() as i32),
+        kg_node_count,
+        kg_edge_count,
+    }
+}
+
+pub fn decode_job_response(job: &Job) -> Option<Vec<u8>> {
+    let job_response = job.response.as_ref()?;
+    match job.response_encoding.as_deref() {
+        Some("gzip") => match gunzip(job_response.as_slice()) {
+            Ok(decoded) => Some(decoded),
+            Err(e) => {
+                warn!("Could not decompress response of job {}: {}", job.id, e);
+                None
+            }
+        },
+        _ => Some(job_response.clone()),
+    }
+}
+
+pub fn build_failure_response(query: &AsyncQuery, status: &str, description: String) -> Response {
+    let mut message = query.message.clone();
+    message.results = Some(vec![]);
+    message.knowledge_graph = Some(KnowledgeGraph::new(HashMap::new(), HashMap::new()));
+
+    let mut res = Response::new(message);
+    res.status = Some(status.to_string());
+    res.description = Some(description);
+    res.workflow = query.workflow.clone();
+    res.biolink_version = Some(env::var("BIOLINK_VERSION").unwrap_or("3.1.2".to_string()));
+    res.schema_version = Some(env::var("TRAPI_VERSION").unwrap_or("1.4.0".to_string()));
+    res
+}
+
+fn parse_job_query(job: &Job) -> Result<AsyncQuery, CQSError> {
+    Ok(serde_json::from_slice(job.query.as_slice())?)
+}
+
+async fn process_asyncquery_job(mut job: Job, lease_seconds: i64, job_timeout: Duration) {
+    info!("Processing Job: {}", job.id);
+
+    let progress_log = ProgressLog::new(Some(job.id));
+    let query = match parse_job_query(&job) {
+        Ok(query) => query,
+        Err(e) => {
+            // without a query there is no callback to send the failure to
+            let failure_reason = format!("Could not read the job's query: {}", e);
+            error!("Failing Job: {}, {}", job.id, failure_reason);
+            progress_log.error(Some("job_failed"), failure_reason.clone()).await;
+            job.status = JobStatus::Failed;
+            job.failure_reason = Some(failure_reason);
             job.date_finished = Some(Utc::now().naive_utc());
-            job.response = None;
+            if let Err(e) = JOB_STORE.finish(&job, &CQS_INSTANCE_ID).await {
+                error!("Could not finish job {}: {}", job.id, e);
+            }
+            return;
+        }
+    };
+    progress_log.info(Some("job_started"), format!("Job started on attempt {}", job.attempts)).await;
+
+    let cqs_templates = match select_templates(job.template_names.as_ref()) {
+        Ok(cqs_templates) => cqs_templates,
+        Err(e) => {
+            // a template named when the job was submitted has since been removed
+            let failure_reason = e.to_string();
+            progress_log.error(Some("job_failed"), failure_reason.clone()).await;
+            let mut res = build_failure_response(&query, "Failed", failure_reason.clone());
+            res.logs = Some(progress_log.entries());
+            job.set_response(encode_job_response(&res));
             job.status = JobStatus::Failed;
-            job_actions::update(&job).await;
-        } else {
-            let res = merge_sort_truncate(query.message.clone(), query.workflow.clone(), responses).await;
-
-            job.response = Some(serde_json::to_vec(&res).expect("Could not serialize response"));
+            job.failure_reason = Some(failure_reason);
             job.date_finished = Some(Utc::now().naive_utc());
-            job.status = JobStatus::Completed;
-            job_actions::update(&job).await;
+            finish_asyncquery_job(&job, &query).await;
+            return;
+        }
+    };
+
+    let workflow_runner_url = workflow_runner_url();
+    let cancellation = register_job_cancellation(job.id);
+    let heartbeat = spawn_lease_heartbeat(job.id, lease_seconds, cancellation.clone());
+    // on timeout or cancellation the in-flight WFR requests are dropped along with the future
+    let outcome = tokio::select! {
+        outcome = timeout(job_timeout, run_templates(&query.message, &cqs_templates, query.bypass_cache.unwrap_or(false), &workflow_runner_url, &progress_log)) => Some(outcome),
+        _ = cancellation.notified() => None,
+    };
+    heartbeat.abort();
+    deregister_job_cancellation(job.id);
 
-            send_callback(query, res).await;
+    let Some(outcome) = outcome else {
+        info!("Job {} was cancelled or lost its lease, abandoning it", job.id);
+        return;
+    };
+
+    job.date_finished = Some(Utc::now().naive_utc());
+    match outcome {
+        Ok(template_runs) => {
+            let res = merge_template_runs(query.message.clone(), query.workflow.clone(), template_runs, &progress_log).await;
+            if res.status == Some("Failed".to_string()) {
+                let failure_reason = res.description.clone().unwrap_or("No template returned results".to_string());
+                progress_log.error(Some("job_failed"), failure_reason.clone()).await;
+                job.status = JobStatus::Failed;
+                job.failure_reason = Some(failure_reason);
+            } else {
+                progress_log.info(Some("job_completed"), "Job completed".to_string()).await;
+                job.status = JobStatus::Completed;
+            }
+            job.set_response(encode_job_response(&res));
         }
+        Err(_) => {
+            warn!("Job {} timed out after {} seconds", job.id, job_timeout.as_secs());
+            let failure_reason = format!("Job timed out after {} seconds", job_timeout.as_secs());
+            progress_log.error(Some("job_timed_out"), failure_reason.clone()).await;
+            let mut res = build_failure_response(&query, "Failed", failure_reason.clone());
+            res.logs = Some(progress_log.entries());
+            job.set_response(encode_job_response(&res));
+            job.status = JobStatus::Failed;
+            job.failure_reason = Some(failure_reason);
+        }
+    };
+
+    finish_asyncquery_job(&job, &query).await;
+}
+
+async fn finish_asyncquery_job(job: &Job, query: &AsyncQuery) {
+    match JOB_STORE.finish(job, &CQS_INSTANCE_ID).await {
+        Ok(true) => enqueue_callback(job.id, &query.callback).await,
+        Ok(false) => warn!("Job {} is no longer leased to {}, not sending callback", job.id, CQS_INSTANCE_ID.as_str()),
+        // the lease will run out & the reaper will requeue the job
+        Err(e) => error!("Could not finish job {}: {}", job.id, e),
     }
-    return;
 }
 
-pub async fn send_callback(query: AsyncQuery, ret: Response) -> bool {
-    info!("ENTERING send_callback(AsyncQuery, Response)");
-    let request_client = REQWEST_CLIENT.get().await;
+pub fn job_max_attempts() -> i32 {
+    env::var("JOB_MAX_ATTEMPTS").ok().and_then(|max_attempts| max_attempts.parse::<i32>().ok()).unwrap_or(3)
+}
 
-    let mut was_successful = false;
-    for attempt in 1..=2 {
-        info!("attempt #{} - sending response to: {}", attempt, &query.callback);
-        match request_client.post(&query.callback).json(&ret).timeout(Duration::from_secs(60)).send().await {
-            Ok(attempt_callback_response) => {
-                let attempt_status_code = attempt_callback_response.status();
-                info!("attempt #{} - attempt_status_code: {}", attempt, attempt_status_code);
-                if attempt_status_code.is_success() {
-                    info!("attempt #{} - successfully sent to callback", attempt);
-                    was_successful = true;
-                }
+/// requeues Running jobs whose lease has expired, failing those out of attempts
+pub async fn reap_expired_asyncquery_jobs() {
+    debug!("reaping expired asyncquery jobs");
+
+    let max_attempts = job_max_attempts();
+
+    let expired_jobs = match JOB_STORE.find_expired_leases().await {
+        Ok(expired_jobs) => expired_jobs,
+        Err(e) => {
+            warn!("Could not find expired leases: {}", e);
+            return;
+        }
+    };
+
+    for mut job in expired_jobs.into_iter() {
+        job.lease_owner = None;
+        job.lease_expires = None;
+
+        if job.attempts < max_attempts {
+            info!("Requeueing Job: {} after attempt {} of {}", job.id, job.attempts, max_attempts);
+            ProgressLog::new(Some(job.id))
+                .warning(
+                    Some("job_requeued"),
+                    format!("Job lease expired, requeueing after attempt {} of {}", job.attempts, max_attempts),
+                )
+                .await;
+            job.status = JobStatus::Queued;
+            job.date_started = None;
+            if let Err(e) = JOB_STORE.update_if_lease_expired(&job).await {
+                warn!("Could not requeue job {}: {}", job.id, e);
             }
+            continue;
+        }
+
+        warn!("Failing Job: {} after {} attempts", job.id, job.attempts);
+        let failure_reason = format!("Job was abandoned after {} attempts", job.attempts);
+        ProgressLog::new(Some(job.id)).error(Some("job_failed"), failure_reason.clone()).await;
+        let query = match parse_job_query(&job) {
+            Ok(query) => Some(query),
             Err(e) => {
-                warn!("attempt #{} - callback error: {}", attempt, e);
+                warn!("Could not read the query of job {}: {}", job.id, e);
+                None
             }
+        };
+        if let Some(query) = &query {
+            job.set_response(encode_job_response(&build_failure_response(query, "Failed", failure_reason.clone())));
         }
-        if was_successful {
-            break;
-        } else {
-            tokio::time::sleep(Duration::from_secs(15)).await;
+        job.status = JobStatus::Failed;
+        job.date_finished = Some(Utc::now().naive_utc());
+        job.failure_reason = Some(failure_reason);
+        match (JOB_STORE.update_if_lease_expired(&job).await, &query) {
+            (Ok(true), Some(query)) => enqueue_callback(job.id, &query.callback).await,
+            (Ok(_), _) => {}
+            (Err(e), _) => warn!("Could not fail job {}: {}", job.id, e),
         }
     }
+}
 
-    was_successful
+pub fn callback_max_attempts() -> i32 {
+    env::var("CALLBACK_MAX_ATTEMPTS")
+        .ok()
+        .and_then(|max_attempts| max_attempts.parse::<i32>().ok())
+        .unwrap_or(8)
 }
 
-pub async fn delete_stale_asyncquery_jobs() {
-    debug!("deleting stale asyncquery jobs");
-    if let Ok(jobs) = job_actions::find_all(None).await {
-        let now = Utc::now().naive_utc();
-        let ids = jobs
-            .into_iter()
-            .filter(|j| {
-                let diff = now - j.date_submitted;
-                diff.num_seconds() > 3600
-            })
-            .map(|j| j.id)
-            .collect_vec();
-        job_actions::delete_many(ids).await;
+pub fn callback_backoff_seconds() -> i64 {
+    env::var("CALLBACK_BACKOFF_SECONDS").ok().and_then(|backoff| backoff.parse::<i64>().ok()).unwrap_or(15)
+}
+
+pub fn callback_poll_seconds() -> u64 {
+    env::var("CALLBACK_POLL_SECONDS").ok().and_then(|poll| poll.parse::<u64>().ok()).unwrap_or(30)
+}
+
+pub fn callback_retry_delay(attempts: i32, backoff_seconds: i64) -> chrono::Duration {
+    let exponent = (attempts - 1).clamp(0, 20) as u32;
+    chrono::Duration::seconds(backoff_seconds.saturating_mul(2_i64.pow(exponent)).min(6 * 3600))
+}
+
+pub async fn enqueue_callback(job_id: i32, url: &str) {
+    match JOB_STORE.insert_callback(&NewCallback::new(job_id, url.to_string())).await {
+        Ok(_) => CALLBACK_WAKEUP.notify_one(),
+        Err(e) => error!("Could not queue callback for job {}: {}", job_id, e),
+    }
+}
+
+/// delivers due callbacks, rescheduling failures with exponential backoff
+pub async fn deliver_pending_callbacks() {
+    debug!("delivering pending callbacks");
+    loop {
+        // long enough for a delivery to time out before another replica could claim it again
+        match JOB_STORE.claim_due_callbacks(10, 120).await {
+            Ok(due_callbacks) if !due_callbacks.is_empty() => {
+                join_all(due_callbacks.into_iter().map(deliver_callback)).await;
+            }
+            Ok(_) => break,
+            Err(e) => {
+                warn!("Could not claim pending callbacks: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+async fn deliver_callback(mut callback: Callback) {
+    info!("attempt #{} - sending response of job {} to: {}", callback.attempts, callback.job_id, callback.url);
+
+    let job_response = match JOB_STORE.find_by_id(callback.job_id).await {
+        Ok(Some(job)) => decode_job_response(&job),
+        _ => None,
+    };
+
+    let Some(job_response) = job_response else {
+        warn!("Job {} has no response, dead-lettering callback {}", callback.job_id, callback.id);
+        callback.status = CallbackStatus::Dead;
+        callback.last_error = Some("Job has no response".to_string());
+        if let Err(e) = JOB_STORE.update_callback(&callback).await {
+            warn!("Could not update callback {}: {}", callback.id, e);
+        }
+        return;
+    };
+
+    let request_client = REQWEST_CLIENT.get().await;
+    let delivery = request_client
+        .post(&callback.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(job_response)
+        .timeout(Duration::from_secs(60))
+        .send()
+        .await;
+
+    match delivery {
+        Ok(callback_response) => {
+            let callback_status_code = callback_response.status();
+            info!("attempt #{} - callback_status_code: {}", callback.attempts, callback_status_code);
+            callback.last_status_code = Some(callback_status_code.as_u16() as i32);
+            callback.last_error = if callback_status_code.is_success() {
+                None
+            } else {
+                Some(callback_response.text().await.unwrap_or_default().chars().take(1000).collect())
+            };
+        }
+        Err(e) => {
+            warn!("attempt #{} - callback error: {}", callback.attempts, e);
+            callback.last_status_code = e.status().map(|sc| sc.as_u16() as i32);
+            callback.last_error = Some(e.to_string());
+        }
+    }
+
+    let now = Utc::now().naive_utc();
+    if callback.last_error.is_none() {
+        callback.status = CallbackStatus::Delivered;
+        callback.date_delivered = Some(now);
+    } else if callback.attempts >= callback_max_attempts() {
+        warn!("Dead-lettering callback {} of job {} after {} attempts", callback.id, callback.job_id, callback.attempts);
+        callback.status = CallbackStatus::Dead;
+    } else {
+        callback.next_attempt = now + callback_retry_delay(callback.attempts, callback_backoff_seconds());
+    }
+    // if this fails the claim runs out & the callback is retried
+    if let Err(e) = JOB_STORE.update_callback(&callback).await {
+        warn!("Could not update callback {}: {}", callback.id, e);
+    }
+}
+
+pub async fn redeliver_callbacks(job: &Job) -> Result<Vec<Callback>, CQSError> {
+    if JOB_STORE.reset_callbacks_by_job_id(job.id).await? == 0 {
+        let query = parse_job_query(job)?;
+        JOB_STORE.insert_callback(&NewCallback::new(job.id, query.callback)).await?;
+    }
+    CALLBACK_WAKEUP.notify_one();
+    JOB_STORE.find_callbacks_by_job_id(job.id).await
+}
+
+pub fn run_migrations_on_startup() -> bool {
+    env::var("RUN_MIGRATIONS").ok().and_then(|run| run.parse::<bool>().ok()).unwrap_or(true)
+}
+
+pub fn allow_legacy_job_ids() -> bool {
+    env::var("ALLOW_LEGACY_JOB_IDS").ok().and_then(|allow| allow.parse::<bool>().ok()).unwrap_or(true)
+}
+
+pub async fn find_job(job_id: &str) -> Result<Job, CQSError> {
+    JOB_STORE
+        .find_by_public_id(job_id, allow_legacy_job_ids())
+        .await?
+        .ok_or_else(|| CQSError::JobNotFound(job_id.to_string()))
+}
+
+fn retention_seconds(value: Option<String>, default: i64) -> Option<i64> {
+    let seconds = value.and_then(|v| v.parse::<i64>().ok()).unwrap_or(default);
+    if seconds > 0 {
+        Some(seconds)
+    } else {
+        None
+    }
+}
+
+pub fn job_retention_policy() -> JobRetentionPolicy {
+    JobRetentionPolicy {
+        completed_seconds: retention_seconds(env::var("JOB_RETENTION_COMPLETED_SECONDS").ok(), 86400),
+        failed_seconds: retention_seconds(env::var("JOB_RETENTION_FAILED_SECONDS").ok(), 86400),
+        unfinished_seconds: retention_seconds(env::var("JOB_RETENTION_UNFINISHED_SECONDS").ok(), 86400),
+        archive_dir: env::var("JOB_ARCHIVE_DIR").ok().filter(|dir| !dir.is_empty()).map(std::path::PathBuf::from),
+    }
+}
+
+fn archive_job_response(archive_dir: &std::path::Path, job_id: &uuid::Uuid, response: &[u8], encoding: Option<&str>) -> std::io::Result<()> {
+    fs::create_dir_all(archive_dir)?;
+    let file_name = match encoding {
+        Some("gzip") => format!("{}.json.gz", job_id),
+        _ => format!("{}.json", job_id),
+    };
+    fs::write(archive_dir.join(file_name), response)
+}
+
+/// deletes the jobs that have outlived the retention policy, archiving them first if JOB_ARCHIVE_DIR is set
+pub async fn delete_expired_asyncquery_jobs() {
+    debug!("deleting expired asyncquery jobs");
+    let policy = job_retention_policy();
+    if policy.is_empty() {
+        return;
+    }
+
+    let deleted = match &policy.archive_dir {
+        Some(archive_dir) => {
+            JOB_STORE
+                .archive_and_delete_expired(&policy, &|job_id, response, encoding| archive_job_response(archive_dir, job_id, response, encoding))
+                .await
+        }
+        None => JOB_STORE.delete_expired(&policy).await,
+    };
+    match deleted {
+        Ok(num_deleted) => debug!("num_deleted: {}", num_deleted),
+        Err(e) => warn!("Could not delete expired asyncquery jobs: {}", e),
     }
 }
 
+pub fn describe_attribute_constraint(ac: &AttributeConstraint) -> String {
+    format!("{} {} {}", ac.id, ac.operator, explanation_value_to_string(&ac.value))
+}
+
 pub fn find_edge_keys_to_remove(ac: trapi_model_rs::AttributeConstraint, edge_map: &HashMap<String, Edge>) -> Vec<String> {
     let mut to_remove = vec![];
 
@@ -870,10 +2048,16 @@ pub fn find_edge_keys_to_remove(ac: trapi_model_rs::AttributeConstraint, edge_ma
 
 #[cfg(test)]
 mod test {
-    use crate::model::{CQSCompositeScoreKey, CQSCompositeScoreValue};
+    use crate::error::CQSError;
+    use crate::model::{AttributeAggregation, AttributeRule, CQSCompositeScoreKey, CQSCompositeScoreValue, IntermediateNode, IntermediateNodeMode, TemplateOutcome};
     use crate::template;
     use crate::template::CQSTemplate;
-    use crate::util::{add_support_graphs, build_node_binding_to_log_odds_data_map, find_edge_keys_to_remove};
+    use crate::util::{
+        add_support_graphs, aggregate_attribute_values, bind_intermediate_nodes, build_meta_knowledge_graph, build_node_binding_to_log_odds_data_map, build_provenance_attributes,
+        callback_retry_delay, compute_query_hash, describe_attribute_constraint, dry_run_templates, find_edge_keys_to_remove, gunzip, gzip, lift_support_path_attributes,
+        parse_template_names, readme_pocs, readme_summary, render_explanation, retention_seconds, select_templates, summarize_template, summarize_template_runs,
+        template_node_id_prefixes, treats_query_ids, ProgressLog, TemplateRun, PINNED_IDS_PLACEHOLDER,
+    };
     use itertools::Itertools;
     use merge_hashmap::Merge;
     use serde_json::{json, Result, Value};
@@ -928,6 +2112,14 @@ mod test {
         assert_eq!(0, edge_map.len());
     }
 
+    #[test]
+    fn describe_attribute_constraints() {
+        let ac = AttributeConstraint::new("biolink:evidence_count".to_string(), "asdf".to_string(), ">".to_string(), 10.into());
+        assert_eq!("biolink:evidence_count > 10", describe_attribute_constraint(&ac));
+        let ac = AttributeConstraint::new("elevate_to_prediction".to_string(), "asdf".to_string(), "==".to_string(), "True".into());
+        assert_eq!("elevate_to_prediction == True", describe_attribute_constraint(&ac));
+    }
+
     #[test]
     fn attribute_constraint_string_equals() {
         let mut edge_map: HashMap<String, Edge> = serde_json::from_value(json!({
@@ -1103,6 +2295,487 @@ mod test {
         assert_eq!(0, edge_map.len());
     }
 
+    #[test]
+    fn query_hash_tracks_rendered_template() {
+        let cqs_query: Box<dyn CQSTemplate> = Box::new(template::ClinicalKPs::new());
+        let first = cqs_query.render_query_template(vec![CURIE::from("MONDO:0004979")]);
+        let second = cqs_query.render_query_template(vec![CURIE::from("MONDO:0004979")]);
+        let third = cqs_query.render_query_template(vec![CURIE::from("MONDO:0009061")]);
+        assert_eq!(compute_query_hash(&cqs_query, &first), compute_query_hash(&cqs_query, &second));
+        assert_ne!(compute_query_hash(&cqs_query, &first), compute_query_hash(&cqs_query, &third));
+
+        let constrained_query: Box<dyn CQSTemplate> = Box::new(template::MultiomicsCTKP::new());
+        let constrained = constrained_query.render_query_template(vec![CURIE::from("MONDO:0004979")]);
+        assert!(constrained.first_edge_attribute_constraint().is_some());
+        let mut unconstrained = constrained.clone();
+        unconstrained.remove_edge_attribute_constraints();
+        assert_ne!(compute_query_hash(&constrained_query, &constrained), compute_query_hash(&constrained_query, &unconstrained));
+
+        let mut rescored = first.clone();
+        rescored.cqs.results_limit = Some(1.0);
+        assert_ne!(compute_query_hash(&cqs_query, &first), compute_query_hash(&cqs_query, &rescored));
+    }
+
+    #[test]
+    fn template_version_falls_back_to_file_hash() {
+        let cqs_query: Box<dyn CQSTemplate> = Box::new(template::ClinicalKPs::new());
+        let mut query_template = cqs_query.render_query_template(vec![CURIE::from("MONDO:0004979")]);
+        query_template.cqs.version = None;
+        let attributes = build_provenance_attributes(&cqs_query, &query_template, "hash");
+        let version = attributes.iter().find(|a| a.original_attribute_name == Some("cqs_template_version".to_string())).unwrap();
+        assert_eq!(json!(format!("sha256:{}", &cqs_query.template_hash()[..12])), version.value);
+
+        query_template.cqs.version = Some("1.2.0".to_string());
+        let attributes = build_provenance_attributes(&cqs_query, &query_template, "hash");
+        let version = attributes.iter().find(|a| a.original_attribute_name == Some("cqs_template_version".to_string())).unwrap();
+        assert_eq!(json!("1.2.0"), version.value);
+    }
+
+    #[test]
+    fn aggregate_attribute_values_by_rule() {
+        let values = vec![json!("clinical_trial_phase_2"), json!(["clinical_trial_phase_3"]), json!("clinical_trial_phase_2")];
+        assert_eq!(
+            Some(json!("clinical_trial_phase_3")),
+            aggregate_attribute_values(&AttributeAggregation::Max, values.clone())
+        );
+        assert_eq!(
+            Some(json!("clinical_trial_phase_2")),
+            aggregate_attribute_values(&AttributeAggregation::Min, values.clone())
+        );
+        assert_eq!(
+            Some(json!("clinical_trial_phase_2")),
+            aggregate_attribute_values(&AttributeAggregation::First, values.clone())
+        );
+        assert_eq!(Some(json!(3)), aggregate_attribute_values(&AttributeAggregation::Count, values.clone()));
+        assert_eq!(
+            Some(json!(["clinical_trial_phase_2", "clinical_trial_phase_3"])),
+            aggregate_attribute_values(&AttributeAggregation::List, values)
+        );
+        assert_eq!(
+            Some(json!(10)),
+            aggregate_attribute_values(&AttributeAggregation::Max, vec![json!(9), json!("10"), json!(2)])
+        );
+        assert_eq!(None, aggregate_attribute_values(&AttributeAggregation::Max, vec![]));
+
+        let values = vec![json!("pre_clinical_research_phase"), json!("clinical_trial_phase_3"), json!("not_provided")];
+        assert_eq!(
+            Some(json!("clinical_trial_phase_3")),
+            aggregate_attribute_values(&AttributeAggregation::Max, values.clone())
+        );
+        assert_eq!(Some(json!("not_provided")), aggregate_attribute_values(&AttributeAggregation::Min, values));
+    }
+
+    #[test]
+    fn lift_attributes_from_multi_hop_support_path() {
+        let edge_map: HashMap<String, Edge> = serde_json::from_value(json!({
+            "e0": {
+              "subject": "CHEBI:1",
+              "predicate": "biolink:affects",
+              "object": "NCBIGene:1",
+              "sources": [],
+              "attributes": [ { "attribute_type_id": "biolink:max_research_phase", "value": "clinical_trial_phase_2" } ]
+            },
+            "e1": {
+              "subject": "NCBIGene:1",
+              "predicate": "biolink:gene_associated_with_condition",
+              "object": "MONDO:1",
+              "sources": [],
+              "attributes": [ { "attribute_type_id": "biolink:max_research_phase", "value": "clinical_trial_phase_3" } ]
+            }
+        }))
+        .unwrap();
+
+        let rules = vec![AttributeRule {
+            attribute_type_id: "biolink:max_research_phase".to_string(),
+            aggregation: AttributeAggregation::Max,
+            output_attribute_type_id: None,
+        }];
+        let attributes = lift_support_path_attributes(&rules, &vec!["e0".to_string(), "e1".to_string()], &edge_map);
+        assert_eq!(1, attributes.len());
+        assert_eq!(json!("clinical_trial_phase_3"), attributes[0].value);
+    }
+
+    #[test]
+    fn render_explanation_from_bindings() {
+        let knowledge_graph: trapi_model_rs::KnowledgeGraph = serde_json::from_value(json!({
+            "nodes": {
+                "CHEBI:1": { "name": "aspirin", "categories": ["biolink:SmallMolecule"] },
+                "MONDO:1": { "categories": ["biolink:Disease"] }
+            },
+            "edges": {
+                "kg_e0": {
+                  "subject": "CHEBI:1",
+                  "predicate": "biolink:in_clinical_trials_for",
+                  "object": "MONDO:1",
+                  "sources": [ { "resource_id": "infores:ctkp", "resource_role": "primary_knowledge_source" } ],
+                  "attributes": [ { "attribute_type_id": "biolink:max_research_phase", "value": "clinical_trial_phase_3" } ]
+                }
+            }
+        }))
+        .unwrap();
+
+        let node_bindings = BTreeMap::from([
+            ("n0".to_string(), vec![NodeBinding::new(CURIE::from("CHEBI:1"))]),
+            ("n1".to_string(), vec![NodeBinding::new(CURIE::from("MONDO:1"))]),
+        ]);
+        let edge_bindings = BTreeMap::from([("e0".to_string(), vec![EdgeBinding::new("kg_e0".to_string())])]);
+
+        let explanation = render_explanation(
+            "{n0} is in {e0.max_research_phase} for {n1} per {e0.primary_knowledge_source} ({e1})",
+            &node_bindings,
+            &edge_bindings,
+            &knowledge_graph,
+        );
+        assert_eq!("aspirin is in clinical_trial_phase_3 for MONDO:1 per infores:ctkp (unknown)", explanation);
+    }
+
+    #[test]
+    fn bind_intermediate_gene_nodes() {
+        let template_node_bindings = BTreeMap::from([
+            ("n0".to_string(), vec![NodeBinding::new(CURIE::from("MONDO:1"))]),
+            ("n1".to_string(), vec![NodeBinding::new(CURIE::from("NCBIGene:1"))]),
+            ("n2".to_string(), vec![NodeBinding::new(CURIE::from("NCBIGene:2"))]),
+            ("n3".to_string(), vec![NodeBinding::new(CURIE::from("CHEBI:1"))]),
+        ]);
+        let intermediate_nodes = vec![
+            IntermediateNode {
+                template_node_id: "n1".to_string(),
+                binding_id: Some("gene".to_string()),
+                mode: IntermediateNodeMode::NodeBinding,
+            },
+            IntermediateNode {
+                template_node_id: "n2".to_string(),
+                binding_id: None,
+                mode: IntermediateNodeMode::AuxiliaryGraph,
+            },
+        ];
+
+        let mut new_node_bindings = BTreeMap::new();
+        let auxiliary_graph_attributes = bind_intermediate_nodes(&intermediate_nodes, &template_node_bindings, &mut new_node_bindings);
+        assert_eq!(Some(&vec![NodeBinding::new(CURIE::from("NCBIGene:1"))]), new_node_bindings.get("gene"));
+        assert_eq!(1, auxiliary_graph_attributes.len());
+        assert_eq!(Some("n2".to_string()), auxiliary_graph_attributes[0].original_attribute_name);
+        assert_eq!(json!(["NCBIGene:2"]), auxiliary_graph_attributes[0].value);
+    }
+
+    #[tokio::test]
+    async fn progress_log_collects_trapi_log_entries() {
+        let progress_log = ProgressLog::new(None);
+        progress_log.info(Some("template_started"), "ClinicalKPs: started".to_string()).await;
+        progress_log.error(None, "ClinicalKPs: no response from WFR".to_string()).await;
+
+        let entries = serde_json::to_value(progress_log.entries()).unwrap();
+        assert_eq!(json!("INFO"), entries[0]["level"]);
+        assert_eq!(json!("template_started"), entries[0]["code"]);
+        assert_eq!(json!("ERROR"), entries[1]["level"]);
+        assert_eq!(json!("ClinicalKPs: no response from WFR"), entries[1]["message"]);
+    }
+
+    #[test]
+    fn callback_retry_delay_backs_off_exponentially() {
+        assert_eq!(15, callback_retry_delay(1, 15).num_seconds());
+        assert_eq!(30, callback_retry_delay(2, 15).num_seconds());
+        assert_eq!(120, callback_retry_delay(4, 15).num_seconds());
+        assert_eq!(6 * 3600, callback_retry_delay(15, 15).num_seconds());
+    }
+
+    #[test]
+    fn gzip_round_trip() {
+        let data = json!({"message": {"results": []}, "logs": []}).to_string();
+        let compressed = gzip(data.as_bytes()).unwrap();
+        assert_eq!(&compressed[..2], &[0x1f, 0x8b]);
+        assert_eq!(data.as_bytes(), gunzip(compressed.as_slice()).unwrap().as_slice());
+    }
+
+    #[test]
+    fn parse_retention_seconds() {
+        assert_eq!(Some(86400), retention_seconds(None, 86400));
+        assert_eq!(Some(600), retention_seconds(Some("600".to_string()), 86400));
+        assert_eq!(Some(86400), retention_seconds(Some("soon".to_string()), 86400));
+        assert_eq!(None, retention_seconds(Some("0".to_string()), 86400));
+    }
+
+    #[test]
+    fn summarize_partial_template_runs() {
+        let template_run = |name: &str, outcome: TemplateOutcome| TemplateRun {
+            name: name.to_string(),
+            outcome,
+            response: None,
+        };
+
+        let (status, description) = summarize_template_runs(&vec![
+            template_run("ClinicalKPs", TemplateOutcome::Succeeded),
+            template_run("OpenPredict", TemplateOutcome::Succeeded),
+        ]);
+        assert_eq!("Success", status);
+        assert_eq!("succeeded: ClinicalKPs, OpenPredict", description);
+
+        let (status, description) = summarize_template_runs(&vec![
+            template_run("ClinicalKPs", TemplateOutcome::Succeeded),
+            template_run("OpenPredict", TemplateOutcome::Failed),
+            template_run("CAMKP", TemplateOutcome::TimedOut),
+        ]);
+        assert_eq!("PartialSuccess", status);
+        assert_eq!("succeeded: ClinicalKPs; failed: OpenPredict; timed out: CAMKP", description);
+
+        let (status, _description) = summarize_template_runs(&vec![template_run("OpenPredict", TemplateOutcome::Failed)]);
+        assert_eq!("Failed", status);
+    }
+
+    #[test]
+    fn meta_knowledge_graph_from_templates() {
+        let meta_kg = serde_json::to_value(build_meta_knowledge_graph(&crate::WHITELISTED_TEMPLATE_QUERIES).unwrap()).unwrap();
+        let edges = meta_kg["edges"].as_array().unwrap();
+        assert!(edges
+            .iter()
+            .all(|edge| edge["predicate"] == "biolink:treats" && edge["knowledge_types"] == json!(["inferred"])));
+
+        let chemical_edge = edges
+            .iter()
+            .find(|edge| edge["subject"] == "biolink:ChemicalEntity" && edge["object"] == "biolink:DiseaseOrPhenotypicFeature")
+            .unwrap();
+        let attributes = chemical_edge["attributes"].as_array().unwrap();
+        assert_eq!(1, attributes.len());
+        assert_eq!(json!("biolink:max_research_phase"), attributes[0]["attribute_type_id"]);
+        assert!(edges.iter().any(|edge| edge["subject"] == "biolink:Drug" && edge["object"] == "biolink:Disease"));
+
+        edges.iter().for_each(|edge| {
+            assert!(meta_kg["nodes"][edge["subject"].as_str().unwrap()]["id_prefixes"]
+                .as_array()
+                .unwrap()
+                .contains(&json!("CHEBI")));
+            assert!(meta_kg["nodes"][edge["object"].as_str().unwrap()]["id_prefixes"]
+                .as_array()
+                .unwrap()
+                .contains(&json!("MONDO")));
+        });
+    }
+
+    #[test]
+    fn template_id_prefixes_override_defaults() {
+        let cqs_query = template::ClinicalKPs::new();
+        let mut query_template = cqs_query.render_query_template(vec![]);
+        assert!(template_node_id_prefixes(&query_template, "n3", "biolink:Drug").contains(&"CHEBI".to_string()));
+        assert!(template_node_id_prefixes(&query_template, "n0", "biolink:Disease").contains(&"MONDO".to_string()));
+        assert!(template_node_id_prefixes(&query_template, "n2", "biolink:Gene").is_empty());
+
+        query_template.cqs.id_prefixes = Some(BTreeMap::from([("n3".to_string(), vec!["DRUGBANK".to_string()])]));
+        assert_eq!(vec!["DRUGBANK".to_string()], template_node_id_prefixes(&query_template, "n3", "biolink:Drug"));
+        assert!(template_node_id_prefixes(&query_template, "n0", "biolink:Disease").contains(&"MONDO".to_string()));
+    }
+
+    #[test]
+    fn parse_template_readme() {
+        let readme = "## Path A\n\n### Use Case\n\nPath A was developed by the TCDC\nin support of MVP1.\n\n**SMEs**\n\n- Dr. Michael Knowles, UNC\n- Dr. Margaret Leigh, UNC\n\n### Assessment\n\n- not a poc\n";
+        assert_eq!(Some("Path A was developed by the TCDC in support of MVP1.".to_string()), readme_summary(readme));
+        assert_eq!(vec!["Dr. Michael Knowles, UNC".to_string(), "Dr. Margaret Leigh, UNC".to_string()], readme_pocs(readme));
+        assert_eq!(None, readme_summary("# Description\n"));
+        assert!(readme_pocs("# Description\nno contacts listed").is_empty());
+    }
+
+    #[test]
+    fn summarize_open_predict_template() {
+        let cqs_template: Box<dyn CQSTemplate> = Box::new(template::OpenPredict::new());
+        let summary = summarize_template(&cqs_template);
+        assert_eq!("OpenPredict", summary.name);
+        assert!(summary.summary.unwrap().starts_with("Simple 1-hop query"));
+        assert_eq!(vec!["biolink:treats".to_string()], summary.predicates);
+        assert_eq!(vec!["infores:openpredict".to_string()], summary.allowlist);
+        assert_eq!(2, summary.edge_sources.len());
+        let query_graph = summary.query.message.query_graph.unwrap();
+        assert_eq!(Some(vec![CURIE::from(PINNED_IDS_PLACEHOLDER)]), query_graph.nodes.get("n1").unwrap().ids);
+    }
+
+    #[test]
+    fn select_named_templates() {
+        assert_eq!(None, parse_template_names(None));
+        assert_eq!(None, parse_template_names(Some(" , ")));
+        let template_names = parse_template_names(Some("ClinicalKPs, OpenPredict")).unwrap();
+        assert_eq!(vec!["ClinicalKPs".to_string(), "OpenPredict".to_string()], template_names);
+
+        let cqs_templates = select_templates(Some(&template_names)).unwrap();
+        assert_eq!(
+            vec!["ClinicalKPs", "OpenPredict"],
+            cqs_templates.iter().map(|cqs_template| cqs_template.name()).collect_vec()
+        );
+        assert_eq!(crate::WHITELISTED_TEMPLATE_QUERIES.len(), select_templates(None).unwrap().len());
+        assert!(matches!(
+            select_templates(Some(&vec!["ClinicalKPs".to_string(), "Nope".to_string()])),
+            Err(CQSError::InvalidQuery(_))
+        ));
+    }
+
+    #[test]
+    fn dry_run_renders_template_queries() {
+        let query: Query = serde_json::from_value(json!({
+            "message": {
+                "query_graph": {
+                    "nodes": {"drug": {"categories": ["biolink:ChemicalEntity"]}, "disease": {"categories": ["biolink:Disease"], "ids": ["MONDO:0004979"]}},
+                    "edges": {"t_edge": {"subject": "drug", "object": "disease", "predicates": ["biolink:treats"], "knowledge_type": "inferred"}}
+                }
+            }
+        }))
+        .unwrap();
+        let cqs_templates = select_templates(Some(&vec!["OpenPredict".to_string()])).unwrap();
+
+        let dry_runs = dry_run_templates(&query.message, &cqs_templates, true);
+        assert_eq!(1, dry_runs.len());
+        assert_eq!("OpenPredict", dry_runs[0].name);
+        assert_eq!(Some(true), dry_runs[0].query.bypass_cache);
+        let query_graph = dry_runs[0].query.message.query_graph.as_ref().unwrap();
+        assert_eq!(Some(vec![CURIE::from("MONDO:0004979")]), query_graph.nodes.get("n1").unwrap().ids);
+        assert!(query_graph.edges.values().all(|edge| edge.attribute_constraints.is_none()));
+    }
+
+    #[test]
+    fn validate_treats_query_shape() {
+        let query_with_edge = |predicate: &str, knowledge_type: &str, disease_ids: Value| -> Query {
+            serde_json::from_value(json!({
+                "message": {
+                    "query_graph": {
+                        "nodes": {"drug": {"categories": ["biolink:ChemicalEntity"]}, "disease": {"categories": ["biolink:Disease"], "ids": disease_ids}},
+                        "edges": {"t_edge": {"subject": "drug", "object": "disease", "predicates": [predicate], "knowledge_type": knowledge_type}}
+                    }
+                }
+            }))
+            .unwrap()
+        };
+
+        let query = query_with_edge("biolink:treats", "inferred", json!(["MONDO:0004979"]));
+        assert_eq!(vec![CURIE::from("MONDO:0004979")], *treats_query_ids(&query.message).unwrap());
+
+        let query = query_with_edge("biolink:treats", "lookup", json!(["MONDO:0004979"]));
+        assert!(matches!(treats_query_ids(&query.message), Err(CQSError::UnsupportedQuery(_))));
+
+        let query = query_with_edge("biolink:affects", "inferred", json!(["MONDO:0004979"]));
+        assert!(matches!(treats_query_ids(&query.message), Err(CQSError::UnsupportedQuery(_))));
+
+        let query = query_with_edge("biolink:treats", "inferred", json!(null));
+        assert!(matches!(treats_query_ids(&query.message), Err(CQSError::UnsupportedQuery(_))));
+
+        let query: Query = serde_json::from_value(json!({"message": {}})).unwrap();
+        assert!(matches!(treats_query_ids(&query.message), Err(CQSError::InvalidQuery(_))));
+    }
+
+    fn clinical_kps_response() -> Response {
+        serde_json::from_value(json!({
+            "message": {
+                "knowledge_graph": {
+                    "nodes": {
+                        "MONDO:1": { "name": "asthma", "categories": ["biolink:Disease"] },
+                        "CHEBI:2": { "name": "theophylline", "categories": ["biolink:SmallMolecule"] },
+                        "NCBIGene:3": { "name": "ADORA1", "categories": ["biolink:Gene"] },
+                        "CHEBI:4": { "name": "caffeine", "categories": ["biolink:Drug"] }
+                    },
+                    "edges": {
+                        "kg_e0": {
+                          "subject": "MONDO:1",
+                          "predicate": "biolink:correlated_with",
+                          "object": "CHEBI:2",
+                          "sources": [ { "resource_id": "infores:cohd", "resource_role": "primary_knowledge_source" } ],
+                          "attributes": [ { "attribute_type_id": "biolink:p_value", "value": 0.01 } ]
+                        },
+                        "kg_e1": {
+                          "subject": "CHEBI:2",
+                          "predicate": "biolink:physically_interacts_with",
+                          "object": "NCBIGene:3",
+                          "sources": [ { "resource_id": "infores:molepro", "resource_role": "primary_knowledge_source" } ]
+                        },
+                        "kg_e2": {
+                          "subject": "CHEBI:4",
+                          "predicate": "biolink:physically_interacts_with",
+                          "object": "NCBIGene:3",
+                          "sources": [ { "resource_id": "infores:molepro", "resource_role": "primary_knowledge_source" } ]
+                        },
+                        "kg_e3": {
+                          "subject": "NCBIGene:3",
+                          "predicate": "biolink:gene_associated_with_condition",
+                          "object": "MONDO:1",
+                          "sources": [ { "resource_id": "infores:ctd", "resource_role": "primary_knowledge_source" } ],
+                          "attributes": [ { "attribute_type_id": "biolink:p_value", "value": 0.04 } ]
+                        }
+                    }
+                },
+                "results": [
+                    {
+                        "node_bindings": {
+                            "n0": [ { "id": "MONDO:1" } ],
+                            "n1": [ { "id": "CHEBI:2" } ],
+                            "n2": [ { "id": "NCBIGene:3" } ],
+                            "n3": [ { "id": "CHEBI:4" } ]
+                        },
+                        "analyses": [
+                            {
+                                "resource_id": "infores:aragorn",
+                                "edge_bindings": {
+                                    "e0": [ { "id": "kg_e0" } ],
+                                    "e1": [ { "id": "kg_e1" } ],
+                                    "e2": [ { "id": "kg_e2" } ],
+                                    "e3": [ { "id": "kg_e3" } ]
+                                }
+                            }
+                        ]
+                    }
+                ]
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn clinical_kps_support_graphs() {
+        let query: Query = serde_json::from_value(json!({
+            "message": {
+                "query_graph": {
+                    "nodes": {"drug": {"categories": ["biolink:ChemicalEntity"]}, "disease": {"categories": ["biolink:Disease"], "ids": ["MONDO:1"]}},
+                    "edges": {"t_edge": {"subject": "drug", "object": "disease", "predicates": ["biolink:treats"], "knowledge_type": "inferred"}}
+                }
+            }
+        }))
+        .unwrap();
+        let cqs_query: Box<dyn CQSTemplate> = Box::new(template::ClinicalKPs::new());
+        let query_template = cqs_query.render_query_template(vec![CURIE::from("MONDO:1")]);
+
+        let mut response = clinical_kps_response();
+        add_support_graphs(&mut response, query.message.query_graph.as_ref().unwrap(), &cqs_query, &query_template, "hash");
+
+        let result = &response.message.results.as_ref().unwrap()[0];
+        assert_eq!(vec!["disease", "drug"], result.node_bindings.keys().collect::<Vec<_>>());
+        let edge_binding = &result.analyses[0].edge_bindings.get("t_edge").unwrap()[0];
+        let treats_edge = response.message.knowledge_graph.as_ref().unwrap().edges.get(&edge_binding.id).unwrap();
+        assert_eq!(CURIE::from("CHEBI:4"), treats_edge.subject);
+        assert_eq!(CURIE::from("MONDO:1"), treats_edge.object);
+
+        let treats_attribute = |name: &str| {
+            treats_edge
+                .attributes
+                .as_ref()
+                .unwrap()
+                .iter()
+                .find(|a| a.original_attribute_name == Some(name.to_string()))
+                .map(|a| a.value.clone())
+        };
+        assert_eq!(
+            Some(json!(
+                "caffeine physically interacts with ADORA1, which gene associated with condition asthma; asthma correlated with theophylline per infores:cohd"
+            )),
+            treats_attribute("cqs_explanation")
+        );
+        assert_eq!(Some(json!(0.01)), treats_attribute("min(biolink:p_value)"));
+
+        let auxiliary_graphs = response.message.auxiliary_graphs.as_ref().unwrap();
+        assert_eq!(1, auxiliary_graphs.len());
+        let auxiliary_graph_attributes = auxiliary_graphs.values().next().unwrap().attributes.as_ref().unwrap();
+        let intermediate_ids = |name: &str| {
+            auxiliary_graph_attributes
+                .iter()
+                .find(|a| a.original_attribute_name == Some(name.to_string()))
+                .map(|a| a.value.clone())
+        };
+        assert_eq!(Some(json!(["CHEBI:2"])), intermediate_ids("associated_chemicals"));
+        assert_eq!(Some(json!(["NCBIGene:3"])), intermediate_ids("shared_genes"));
+    }
+
     #[test]
     #[ignore]
     fn simple_merge() {